@@ -1,7 +1,42 @@
-use std::{env, path::PathBuf};
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=build.rs");
+    link()
+}
+
+/// Links nothing, `lua_shared` is loaded at runtime.
+#[cfg(feature = "dynamic")]
+fn link() -> Result<(), Box<dyn std::error::Error>> {
+    Ok(())
+}
+
+/// Builds LuaJIT from source and links it statically.
+#[cfg(all(feature = "luajit-vendored", not(feature = "dynamic")))]
+fn link() -> Result<(), Box<dyn std::error::Error>> {
+    luajit_src::Build::new().build().print_cargo_metadata();
+    Ok(())
+}
+
+/// Links the system LuaJIT found by pkg-config.
+#[cfg(all(
+    feature = "luajit-system",
+    not(any(feature = "luajit-vendored", feature = "dynamic"))
+))]
+fn link() -> Result<(), Box<dyn std::error::Error>> {
+    pkg_config::Config::new()
+        .atleast_version("2.1")
+        .probe("luajit")?;
+    Ok(())
+}
+
+/// Links GMod's lua_shared from `lib/`.
+#[cfg(not(any(
+    feature = "luajit-system",
+    feature = "luajit-vendored",
+    feature = "dynamic"
+)))]
+fn link() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{env, path::PathBuf};
+
     let triple = &env::var("TARGET")?;
     let mut target = triple.split("-");
     let arch = target.next().unwrap_or("x86_64");
@@ -12,8 +47,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "x86_64" => &[".", "bin/linux64/", "linux64"][..],
         _ => &[][..],
     };
-    for search_path in search_paths {
-        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", search_path);
+    // Extra entries for layouts the defaults don't cover, separated like `PATH`.
+    println!("cargo:rerun-if-env-changed=LUA_SHARED_RPATH");
+    println!("cargo:rerun-if-env-changed=LUA_SHARED_LINK_SEARCH");
+    let extra_search_paths = env::var_os("LUA_SHARED_RPATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut rpaths = Vec::new();
+    for search_path in search_paths
+        .iter()
+        .map(PathBuf::from)
+        .chain(extra_search_paths)
+    {
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", search_path.display());
+        rpaths.push(search_path);
+    }
+    // Link args don't reach dependents, their build scripts get this as `DEP_LUA_SHARED_RPATH`.
+    println!("cargo:rpath={}", env::join_paths(rpaths)?.to_string_lossy());
+    if let Some(paths) = env::var_os("LUA_SHARED_LINK_SEARCH") {
+        for path in env::split_paths(&paths) {
+            println!("cargo:rustc-link-search=native={}", path.display());
+        }
     }
     let mut link_search_path = PathBuf::from(&env::var("CARGO_MANIFEST_DIR")?);
     link_search_path.push("lib");
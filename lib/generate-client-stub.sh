@@ -0,0 +1,14 @@
+#!/bin/sh
+# Regenerates lib/i686/linux/lua_shared.so, used by the `client` feature on 32-bit linux.
+#
+# It is a link-only stub: an empty function for every `lua*` function that lua_shared_srv.so
+# exports (the client and server libraries export the same Lua API). The game's real
+# lua_shared.so is the one loaded at runtime, found through the rpaths set by build.rs.
+#
+# Needs nm and a gcc that can build 32-bit code (-m32), no 32-bit libc is required.
+set -eu
+cd "$(dirname "$0")/i686/linux"
+nm -D --defined-only lua_shared_srv.so |
+    awk '$2 == "T" && $3 ~ /^lua/ { print "void " $3 "(void) {}" }' >lua_shared_stub.c
+gcc -m32 -shared -nostdlib -fPIC -Wl,-soname,lua_shared.so -o lua_shared.so lua_shared_stub.c
+rm lua_shared_stub.c
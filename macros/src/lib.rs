@@ -0,0 +1,159 @@
+//! Procedural macros for `lua-shared`: `embed_lua!`, re-exported from the crate root, and `#[lua_test]`, re-exported from `lua_shared::testing` with the `testing` feature.
+
+use proc_macro::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut error: TokenStream = format!("::core::compile_error!({:?});", message)
+        .parse()
+        .unwrap();
+    error = error
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(span);
+            token
+        })
+        .collect();
+    error
+}
+
+/// Turns `fn name(state: lua_State) { ... }` into a `#[test]` that runs it on a fresh state.
+///
+/// The state has the standard libraries opened and the crate's `gmod13_open` already run, unless the attribute is written as `#[lua_test(no_open)]`.
+/// The function may return `()` or a `Result`; see `lua_shared::testing::run_test`.
+#[proc_macro_attribute]
+pub fn lua_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let open = match attr.to_string().trim() {
+        "" => true,
+        "no_open" => false,
+        _ => {
+            return compile_error(
+                "expected `#[lua_test]` or `#[lua_test(no_open)]`",
+                Span::call_site(),
+            )
+        }
+    };
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+
+    // Outer attributes like `#[ignore]` go on the generated test.
+    let mut attributes = TokenStream::new();
+    let mut index = 0;
+    while let (Some(TokenTree::Punct(punct)), Some(TokenTree::Group(group))) =
+        (tokens.get(index), tokens.get(index + 1))
+    {
+        if punct.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        attributes.extend(tokens[index..index + 2].iter().cloned());
+        index += 2;
+    }
+    let mut function = tokens[index..].to_vec();
+    let Some(position) = function
+        .iter()
+        .position(|token| matches!(token, TokenTree::Ident(ident) if ident.to_string() == "fn"))
+    else {
+        return compile_error(
+            "`#[lua_test]` can only be used on functions",
+            Span::call_site(),
+        );
+    };
+    let Some(TokenTree::Ident(name)) = function.get(position + 1).cloned() else {
+        return compile_error("expected a function name", Span::call_site());
+    };
+    function[position + 1] = TokenTree::Ident(Ident::new("__lua_test", name.span()));
+
+    let open = if open {
+        "Some({ unsafe extern \"C\" { fn gmod13_open(state: ::lua_shared::lua_State) -> i32; } gmod13_open as unsafe extern \"C\" fn(::lua_shared::lua_State) -> i32 })"
+    } else {
+        "None"
+    };
+    let mut body: TokenStream = function.into_iter().collect();
+    body.extend(
+        format!(
+            "::lua_shared::testing::run_test({}, |state| unsafe {{ __lua_test(state) }})",
+            open
+        )
+        .parse::<TokenStream>()
+        .unwrap(),
+    );
+
+    let mut output: TokenStream = "#[test] #[allow(unused_unsafe)]".parse().unwrap();
+    output.extend(attributes);
+    output.extend("fn".parse::<TokenStream>().unwrap());
+    output.extend([
+        TokenTree::Ident(name),
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenStream::new())),
+        TokenTree::Group(Group::new(Delimiter::Brace, body)),
+    ]);
+    output
+}
+
+/// Collects the `.lua` and `.luac` files under `directory`, relative to it and sorted.
+fn lua_files(
+    directory: &std::path::Path,
+    prefix: &str,
+    files: &mut Vec<(String, std::path::PathBuf)>,
+) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(directory)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            lua_files(&path, &format!("{}{}/", prefix, name), files)?;
+        } else if name.ends_with(".lua") || name.ends_with(".luac") {
+            files.push((format!("{}{}", prefix, name), path));
+        }
+    }
+    Ok(())
+}
+
+/// Includes every `.lua` and `.luac` file under a directory and evaluates to a `lua_shared::embed::Embedded` with them.
+///
+/// The directory is relative to the crate root. Files are included with `include_bytes!`, so edits are picked up, but added or removed files only show up once the crate is rebuilt; add `println!("cargo:rerun-if-changed=lua")` to a build script to make that automatic.
+#[proc_macro]
+pub fn embed_lua(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let directory = match tokens.as_slice() {
+        [TokenTree::Literal(literal)] | [TokenTree::Literal(literal), TokenTree::Punct(_)] => {
+            let literal = literal.to_string();
+            match literal
+                .strip_prefix('"')
+                .and_then(|literal| literal.strip_suffix('"'))
+            {
+                Some(directory) if !directory.contains('\\') => directory.to_string(),
+                _ => {
+                    return compile_error("expected a plain string literal", literal_span(&tokens))
+                }
+            }
+        }
+        _ => return compile_error("expected `embed_lua!(\"directory\")`", Span::call_site()),
+    };
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_default()
+        .join(&directory);
+    let mut files = Vec::new();
+    if let Err(err) = lua_files(&root, "", &mut files) {
+        return compile_error(
+            &format!("can't read {}: {}", root.display(), err),
+            literal_span(&tokens),
+        );
+    }
+    let mut output = String::from("::lua_shared::embed::Embedded::new(&[");
+    for (path, full_path) in files {
+        output.push_str(&format!(
+            "::lua_shared::embed::EmbeddedFile {{ path: {:?}, chunk: ::core::include_bytes!({:?}) }},",
+            path,
+            full_path.to_string_lossy()
+        ));
+    }
+    output.push_str("])");
+    output.parse().unwrap()
+}
+
+fn literal_span(tokens: &[TokenTree]) -> Span {
+    tokens
+        .first()
+        .map(|token| token.span())
+        .unwrap_or_else(Span::call_site)
+}
@@ -0,0 +1,147 @@
+//! Lua states that allocate through the Rust global allocator.
+//!
+//! [`Lua::with_allocator`] needs `lua_newstate` with a custom allocator, which 64-bit LuaJIT only supports when it was built with GC64.
+//! The x64 `lua_shared` shipped with Garry's Mod is not, so there it always returns `None`; 32-bit builds and GC64 builds (like `luajit-vendored`) work.
+
+use std::{
+    alloc::{alloc, dealloc, realloc, Layout},
+    cell::Cell,
+    ffi::c_void,
+    ptr::null_mut,
+};
+
+use crate::{atpanic, close, lua_State, newstate_alloc, tolstring};
+
+const ALIGN: usize = 16;
+
+#[derive(Default)]
+struct Allocator {
+    live: Cell<usize>,
+    peak: Cell<usize>,
+    limit: Cell<Option<usize>>,
+}
+
+unsafe extern "C" fn allocator_callback(
+    userdata: *mut c_void,
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+) -> *mut c_void {
+    let allocator = &*userdata.cast::<Allocator>();
+    let old_size = if ptr.is_null() { 0 } else { old_size };
+    if new_size == 0 {
+        if !ptr.is_null() {
+            dealloc(
+                ptr.cast(),
+                Layout::from_size_align_unchecked(old_size, ALIGN),
+            );
+            allocator.live.set(allocator.live.get() - old_size);
+        }
+        return null_mut();
+    }
+    let live = allocator.live.get() - old_size + new_size;
+    if new_size > old_size && allocator.limit.get().is_some_and(|limit| live > limit) {
+        return null_mut();
+    }
+    let Ok(layout) = Layout::from_size_align(new_size, ALIGN) else {
+        return null_mut();
+    };
+    let new_ptr = if ptr.is_null() {
+        alloc(layout)
+    } else {
+        realloc(
+            ptr.cast(),
+            Layout::from_size_align_unchecked(old_size, ALIGN),
+            new_size,
+        )
+    };
+    if !new_ptr.is_null() {
+        allocator.live.set(live);
+        allocator.peak.set(allocator.peak.get().max(live));
+    }
+    new_ptr.cast()
+}
+
+unsafe extern "C" fn panic_callback(state: lua_State) -> i32 {
+    let mut len = 0;
+    let message = tolstring(state, -1, &mut len);
+    let message = if message.is_null() {
+        "(error object is not a string)".into()
+    } else {
+        String::from_utf8_lossy(std::slice::from_raw_parts(message, len))
+    };
+    eprintln!("PANIC: unprotected error in call to Lua API ({})", message);
+    0
+}
+
+/// Memory usage of a [`Lua`] state, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub live: usize,
+    pub peak: usize,
+    pub limit: Option<usize>,
+}
+
+/// Owned Lua state that allocates through the Rust global allocator and keeps track of its memory usage.
+///
+/// Allocations past the limit are refused, which Lua reports as [`Status::MemoryError`](crate::Status::MemoryError) (or a "not enough memory" error).
+/// The state is closed on drop.
+pub struct Lua {
+    state: lua_State,
+    allocator: Box<Allocator>,
+}
+
+impl Lua {
+    /// Creates a new state with an optional memory limit.
+    ///
+    /// Returns `None` if the state can't be created, either because the limit is too small or because the library doesn't support custom allocators (64-bit LuaJIT without GC64, which includes the game's x64 build).
+    pub fn with_allocator(limit: Option<usize>) -> Option<Self> {
+        let allocator = Box::new(Allocator {
+            limit: Cell::new(limit),
+            ..Default::default()
+        });
+        let state = unsafe {
+            newstate_alloc(
+                allocator_callback,
+                allocator.as_ref() as *const Allocator as _,
+            )
+        };
+        if state.is_null() {
+            return None;
+        }
+        unsafe { atpanic(state, panic_callback) };
+        Some(Self { state, allocator })
+    }
+
+    /// Returns the raw state. It stays valid as long as `self` is alive.
+    pub fn as_ptr(&self) -> lua_State {
+        self.state
+    }
+
+    /// Returns the current memory usage.
+    pub fn memory(&self) -> MemoryStats {
+        MemoryStats {
+            live: self.allocator.live.get(),
+            peak: self.allocator.peak.get(),
+            limit: self.allocator.limit.get(),
+        }
+    }
+
+    /// Changes the memory limit. Memory that is already allocated is not affected.
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.allocator.limit.set(limit)
+    }
+
+    /// Resets the peak usage to the current usage.
+    pub fn reset_peak(&self) {
+        self.allocator.peak.set(self.allocator.live.get())
+    }
+}
+
+impl Drop for Lua {
+    fn drop(&mut self) {
+        // Closing can allocate while running finalizers.
+        self.allocator.limit.set(None);
+        unsafe { close(self.state) };
+    }
+}
@@ -0,0 +1,169 @@
+//! `luac` for the bundled `lua_shared`, so precompiled files always match the game's LuaJIT.
+//!
+//! Errors are printed as `file:line: message`, the format editors and CI annotations pick up.
+
+use std::{fs::File, process::ExitCode};
+
+use lua_shared::{self as lua, bytecode, cstr, LError};
+
+const USAGE: &str = "\
+usage: lua-shared-luac [options] [filenames]
+Available options are:
+  -l       list (compiled bytecode)
+  -o name  output to file 'name' (default is \"luac.out\")
+  -p       parse only
+  -s       strip debug information
+  -v       show version information
+  --       stop handling options
+  -        stop handling options and process stdin";
+
+#[derive(Default)]
+struct Options {
+    list: bool,
+    output: Option<String>,
+    parse_only: bool,
+    strip: bool,
+    version: bool,
+    files: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" => options.list = true,
+            "-o" => match args.next() {
+                Some(output) => options.output = Some(output),
+                None => return Err(String::from("'-o' needs argument")),
+            },
+            "-p" => options.parse_only = true,
+            "-s" => options.strip = true,
+            "-v" => options.version = true,
+            "--" => {
+                options.files.extend(args);
+                break;
+            }
+            "-" => {
+                options.files.push(arg);
+                options.files.extend(args);
+                break;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ => {
+                options.files.push(arg);
+                options.files.extend(args);
+                break;
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Compiles `file` (`-` for stdin) and returns its bytecode.
+unsafe fn compile(state: lua::lua_State, file: &str, strip: bool) -> Result<Vec<u8>, String> {
+    let chunk_name = if file == "-" {
+        String::from("=stdin\0")
+    } else {
+        format!("@{}\0", file)
+    };
+    let loaded = if file == "-" {
+        lua::loadx(
+            state,
+            &mut std::io::stdin().lock(),
+            chunk_name.as_ptr(),
+            cstr!("bt"),
+        )
+    } else {
+        let mut reader = File::open(file).map_err(|err| format!("{}: {}", file, err))?;
+        lua::loadx(state, &mut reader, chunk_name.as_ptr(), cstr!("bt"))
+    };
+    loaded.map_err(|err| match err {
+        // Already `file:line: message`.
+        LError::SyntaxError(message) => message,
+        err => format!("{}: {}", file, err),
+    })?;
+
+    let mut bytecode = Vec::new();
+    let dumped = lua::dump_strip(state, &mut bytecode, strip);
+    lua::pop!(state, 1);
+    dumped.map_err(|err| format!("{}: {}", file, err))?;
+    Ok(bytecode)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    if options.files.len() > 1 && !options.parse_only {
+        return Err(String::from(
+            "cannot combine several files into one chunk, compile them one at a time",
+        ));
+    }
+    let mut chunks = Vec::new();
+    unsafe {
+        let state = lua::newstate();
+        lua::Lopenlibs(state);
+        if options.version {
+            lua::getglobal!(state, cstr!("jit"));
+            if lua::get_type(state, -1) == lua::TTABLE {
+                lua::getfield(state, -1, cstr!("version"));
+            } else {
+                lua::pushnil(state);
+            }
+            if lua::isstring(state, -1) {
+                println!("{}", lua::pop_message(state));
+            } else {
+                println!("unknown LuaJIT version");
+                lua::pop!(state, 1);
+            }
+            lua::pop!(state, 1);
+        }
+        let mut errors = Vec::new();
+        for file in &options.files {
+            match compile(state, file, options.strip) {
+                Ok(bytecode) => chunks.push(bytecode),
+                Err(err) => errors.push(err),
+            }
+        }
+        lua::close(state);
+        // Report every file, not only the first broken one.
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+    }
+    if options.list {
+        for chunk in &chunks {
+            let dump = bytecode::parse(chunk).map_err(|err| err.to_string())?;
+            print!("{}", dump);
+        }
+    }
+    if let (false, Some(chunk)) = (options.parse_only, chunks.first()) {
+        let output = options.output.as_deref().unwrap_or("luac.out");
+        let written = if output == "-" {
+            std::io::Write::write_all(&mut std::io::stdout().lock(), chunk)
+        } else {
+            std::fs::write(output, chunk)
+        };
+        written.map_err(|err| format!("{}: {}", output, err))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("lua-shared-luac: {}\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.files.is_empty() && !options.version {
+        eprintln!("lua-shared-luac: no input files given\n{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
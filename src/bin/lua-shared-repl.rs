@@ -0,0 +1,170 @@
+//! Interactive Lua prompt on top of `lua_shared`.
+//!
+//! Works like the stock `lua` prompt: `=expr` prints the values of `expr`, incomplete statements continue on the next line.
+//! Lines starting with `.` are commands, see `.help`.
+
+use std::{fs::File, io::BufWriter};
+
+use lua_shared::{self as lua, cstr};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+const HELP: &str = "\
+.load FILE  run FILE (source or bytecode)
+.dump FILE  write the bytecode of the last chunk entered to FILE
+.help       show this message
+.exit       leave (so does Ctrl-D)";
+
+/// Registry field holding the last chunk entered, for `.dump`.
+const LAST_CHUNK: *const u8 = cstr!("lua-shared-repl.last");
+
+/// Calls the function on top of the stack and prints its results with `print`.
+unsafe fn call_and_print(state: lua::lua_State) -> Result<(), String> {
+    let base = lua::gettop(state);
+    lua::pushcclosure(state, lua::traceback, 0);
+    lua::insert(state, base);
+    let status = lua::pcall(state, 0, -1, base);
+    lua::remove(state, base);
+    if status != lua::Status::Ok {
+        return Err(lua::pop_message(state));
+    }
+    let nrets = lua::gettop(state) - base + 1;
+    if nrets > 0 {
+        lua::getglobal!(state, cstr!("print"));
+        lua::insert(state, base);
+        if lua::pcall(state, nrets, 0, 0) != lua::Status::Ok {
+            return Err(format!(
+                "error calling 'print' ({})",
+                lua::pop_message(state)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Compiles `code`. Returns `Ok(false)` if it is an incomplete statement.
+unsafe fn compile(state: lua::lua_State, code: &str) -> Result<bool, String> {
+    let status = lua::Lloadbufferx(
+        state,
+        code.as_ptr(),
+        code.len(),
+        cstr!("=stdin"),
+        cstr!("t"),
+    );
+    match status {
+        lua::Status::Ok => Ok(true),
+        lua::Status::SyntaxError => {
+            let message = lua::pop_message(state);
+            if message.ends_with("'<eof>'") {
+                Ok(false)
+            } else {
+                Err(message)
+            }
+        }
+        _ => Err(lua::pop_message(state)),
+    }
+}
+
+unsafe fn load(state: lua::lua_State, path: &str) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|err| format!("cannot open {}: {}", path, err))?;
+    let chunk_name = format!("@{}\0", path);
+    lua::loadx(state, &mut file, chunk_name.as_ptr(), cstr!("bt"))
+        .map_err(|err| err.to_string())?;
+    call_and_print(state)
+}
+
+unsafe fn dump(state: lua::lua_State, path: &str) -> Result<(), String> {
+    lua::getfield(state, lua::REGISTRYINDEX, LAST_CHUNK);
+    if lua::get_type(state, -1) != lua::TFUNCTION {
+        lua::pop!(state, 1);
+        return Err(String::from("nothing to dump yet"));
+    }
+    let file = File::create(path).map_err(|err| format!("cannot open {}: {}", path, err))?;
+    let mut writer = BufWriter::new(file);
+    let result = lua::dump(state, &mut writer);
+    lua::pop!(state, 1);
+    result.map_err(|err| err.to_string())?;
+    std::io::Write::flush(&mut writer).map_err(|err| format!("cannot write {}: {}", path, err))
+}
+
+unsafe fn command(state: lua::lua_State, line: &str) -> Result<bool, String> {
+    let (command, argument) = match line.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, ""),
+    };
+    match (command, argument) {
+        (".exit", _) => return Ok(false),
+        (".help", _) => println!("{}", HELP),
+        (".load" | ".dump", "") => return Err(format!("usage: {} FILE", command)),
+        (".load", path) => load(state, path)?,
+        (".dump", path) => dump(state, path)?,
+        _ => return Err(format!("unknown command {}, see .help", command)),
+    }
+    Ok(true)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut editor = DefaultEditor::new()?;
+    unsafe {
+        let state = lua::newstate();
+        lua::Lopenlibs(state);
+        lua::State::open(state);
+        lua::getglobal!(state, cstr!("jit"));
+        lua::getfield(state, -1, cstr!("version"));
+        if lua::isstring(state, -1) {
+            println!("{}", lua::pop_message(state));
+        } else {
+            lua::pop!(state, 1);
+        }
+        lua::pop!(state, 1);
+
+        let mut code = String::new();
+        loop {
+            let prompt = if code.is_empty() { "> " } else { ">> " };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    code.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(err.into()),
+            };
+            if code.is_empty() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(line.as_str());
+                if line.starts_with('.') {
+                    match command(state, line.trim()) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(err) => eprintln!("{}", err),
+                    }
+                    continue;
+                }
+                match line.strip_prefix('=') {
+                    Some(expression) => code = format!("return {}", expression),
+                    None => code = line,
+                }
+            } else {
+                let _ = editor.add_history_entry(line.as_str());
+                code.push('\n');
+                code.push_str(&line);
+            }
+            match compile(state, &code) {
+                Ok(false) => continue,
+                Ok(true) => {
+                    lua::pushvalue(state, -1);
+                    lua::setfield(state, lua::REGISTRYINDEX, LAST_CHUNK);
+                    if let Err(err) = call_and_print(state) {
+                        eprintln!("{}", err);
+                    }
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+            code.clear();
+        }
+        lua::close(state);
+    }
+    Ok(())
+}
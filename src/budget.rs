@@ -0,0 +1,114 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cstr, gethook, gethookcount, gethookmask, hook, lua_Debug, lua_State, pcall, pop_message,
+    sethook, LError, Lerror, Status, MASKCOUNT,
+};
+
+/// Number of instructions between two budget checks.
+const HOOK_INTERVAL: u64 = 1000;
+
+/// Limits for [`pcall_with_budget`]. `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub instructions: Option<u64>,
+    pub wall_time: Option<Duration>,
+}
+
+enum Exceeded {
+    Instructions,
+    WallTime,
+}
+
+struct BudgetState {
+    interval: u64,
+    instructions: Option<u64>,
+    deadline: Option<Instant>,
+    executed: Cell<u64>,
+    exceeded: Cell<Option<Exceeded>>,
+}
+
+thread_local! {
+    static CURRENT: Cell<*const BudgetState> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Makes the hook run at every instruction, so an error caught by a `pcall` of the script is raised again as soon as that `pcall` returns.
+unsafe fn raise_every_instruction(state: lua_State) {
+    sethook(state, Some(hook(budget_hook)), MASKCOUNT, 1);
+}
+
+unsafe extern "C-unwind" fn budget_hook(state: lua_State, _: *mut lua_Debug) {
+    let budget = CURRENT.with(Cell::get);
+    if budget.is_null() {
+        return;
+    }
+    let budget = &*budget;
+    budget.executed.set(budget.executed.get() + budget.interval);
+    if budget
+        .instructions
+        .is_some_and(|limit| budget.executed.get() >= limit)
+    {
+        budget.exceeded.set(Some(Exceeded::Instructions));
+        raise_every_instruction(state);
+        Lerror(state, cstr!("instruction budget exceeded"));
+    }
+    if budget
+        .deadline
+        .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        budget.exceeded.set(Some(Exceeded::WallTime));
+        raise_every_instruction(state);
+        Lerror(state, cstr!("execution timed out"));
+    }
+}
+
+/// Calls a function in protected mode like [`pcall`], aborting it once it runs out of `budget`.
+///
+/// The budget is enforced with a count hook, which replaces any hook that is already set for the duration of the call; the previous hook is restored afterwards.
+/// The hook keeps raising errors once the budget is spent, so scripts can't escape it with their own `pcall`.
+/// Traces that LuaJIT compiled before the call don't run hooks, call `jit.flush()` first if that matters.
+///
+/// On success the results are left on the stack. On failure nothing is left on the stack, and the error is [`LError::BudgetExceeded`], [`LError::Timeout`] or [`LError::RuntimeMessage`] with the error message.
+///
+/// # Safety
+/// The function and its `nargs` arguments must be on top of the stack of `state`, which must belong to the current thread.
+pub unsafe fn pcall_with_budget(
+    state: lua_State,
+    nargs: i32,
+    nrets: i32,
+    budget: Budget,
+) -> std::result::Result<(), LError> {
+    let interval = budget
+        .instructions
+        .map_or(HOOK_INTERVAL, |limit| limit.clamp(1, HOOK_INTERVAL));
+    let budget_state = BudgetState {
+        interval,
+        instructions: budget.instructions,
+        deadline: budget.wall_time.map(|wall_time| Instant::now() + wall_time),
+        executed: Cell::new(0),
+        exceeded: Cell::new(None),
+    };
+    let previous_hook = gethook(state);
+    let previous_mask = gethookmask(state);
+    let previous_count = gethookcount(state);
+    let previous_budget = CURRENT.with(|current| current.replace(&budget_state));
+    sethook(state, Some(hook(budget_hook)), MASKCOUNT, interval as i32);
+    let status = pcall(state, nargs, nrets, 0);
+    sethook(state, previous_hook, previous_mask, previous_count);
+    CURRENT.with(|current| current.set(previous_budget));
+    match (status, budget_state.exceeded.take()) {
+        (Status::Ok, _) => Ok(()),
+        (_, exceeded) => {
+            let message = pop_message(state);
+            Err(match (status, exceeded) {
+                (Status::MemoryError, _) => LError::MemoryError(message),
+                (_, Some(Exceeded::Instructions)) => LError::BudgetExceeded,
+                (_, Some(Exceeded::WallTime)) => LError::Timeout,
+                _ => LError::RuntimeMessage(message),
+            })
+        }
+    }
+}
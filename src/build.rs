@@ -0,0 +1,221 @@
+//! Precompiling Lua files from a build script.
+//!
+//! [`Build`] compiles a directory of `.lua` files to LuaJIT bytecode with the linked `lua_shared`, so syntax errors fail `cargo build` instead of showing up at server start.
+//! The bytecode goes to `OUT_DIR`, together with a `<name>.rs` file that evaluates to an [`Embedded`](crate::embed::Embedded) with every compiled file.
+//! ```no_run
+//! // build.rs
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     lua_shared::build::Build::new("lua").compile()?;
+//!     Ok(())
+//! }
+//! ```
+//! ```ignore
+//! // src/lib.rs
+//! static MODULES: lua_shared::embed::Embedded = include!(concat!(env!("OUT_DIR"), "/lua.rs"));
+//! ```
+//! The build script runs on the host, so it has to be able to load `lua_shared` (and, on linux, `libtier0` and `libvstdlib`); point `LD_LIBRARY_PATH` at the game's `bin` directory if it can't.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    close, dump_strip, exec::load_buffer, lua_State, newstate, pop, LError, LoadMode, Lopenlibs,
+};
+
+/// A compile error in one of the files.
+#[derive(Debug)]
+pub struct CompileError {
+    pub path: PathBuf,
+    /// The message from the Lua parser, starting with `path:line:`.
+    pub message: String,
+}
+
+/// Errors returned by [`Build::compile`].
+#[derive(Debug)]
+pub enum BuildError {
+    Io(PathBuf, std::io::Error),
+    Compile(Vec<CompileError>),
+    NoOutDir,
+    /// `lua_shared` couldn't create a state.
+    NoState,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            BuildError::Compile(errors) => {
+                write!(f, "failed to compile {} Lua file(s)", errors.len())?;
+                for error in errors {
+                    write!(f, "\n{}", error.message)?;
+                }
+                Ok(())
+            }
+            BuildError::NoOutDir => write!(f, "OUT_DIR is not set, not running in a build script?"),
+            BuildError::NoState => write!(f, "could not create a Lua state"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Compiles a directory of Lua files into `OUT_DIR`.
+#[derive(Clone, Debug)]
+pub struct Build {
+    directory: PathBuf,
+    name: Option<String>,
+    strip: bool,
+}
+
+impl Build {
+    /// `directory` is relative to the crate root, like everything else in a build script.
+    pub fn new<PATH>(directory: PATH) -> Self
+    where
+        PATH: Into<PathBuf>,
+    {
+        Self {
+            directory: directory.into(),
+            name: None,
+            strip: true,
+        }
+    }
+
+    /// Sets the name of the output: bytecode goes to `OUT_DIR/<name>/` and the file list to `OUT_DIR/<name>.rs`.
+    ///
+    /// Defaults to the last component of the directory.
+    pub fn name<NAME>(mut self, name: NAME) -> Self
+    where
+        NAME: Into<String>,
+    {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Whether to strip debug info (line numbers, local and upvalue names). Defaults to `true`.
+    pub fn strip(mut self, strip: bool) -> Self {
+        self.strip = strip;
+        self
+    }
+
+    /// Compiles every `.lua` file and returns the path of the generated `.rs` file.
+    ///
+    /// All files are compiled before failing, so every syntax error is reported at once, each also as a `cargo:warning`.
+    pub fn compile(self) -> std::result::Result<PathBuf, BuildError> {
+        let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").ok_or(BuildError::NoOutDir)?);
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .directory
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("lua")),
+        };
+        println!("cargo:rerun-if-changed={}", self.directory.display());
+        let mut files = Vec::new();
+        collect(&self.directory, Path::new(""), &mut files)?;
+        files.sort();
+
+        let output = out_dir.join(&name);
+        let mut errors = Vec::new();
+        let mut list = String::from("lua_shared::embed::Embedded::new(&[\n");
+        unsafe {
+            let state = newstate();
+            if state.is_null() {
+                return Err(BuildError::NoState);
+            }
+            let _guard = StateGuard(state);
+            Lopenlibs(state);
+            for relative in &files {
+                let path = self.directory.join(relative);
+                println!("cargo:rerun-if-changed={}", path.display());
+                let source =
+                    std::fs::read(&path).map_err(|err| BuildError::Io(path.clone(), err))?;
+                match compile(state, &source, &path, self.strip) {
+                    Ok(bytecode) => {
+                        let target = output.join(relative);
+                        if let Some(parent) = target.parent() {
+                            std::fs::create_dir_all(parent)
+                                .map_err(|err| BuildError::Io(parent.to_path_buf(), err))?;
+                        }
+                        std::fs::write(&target, bytecode)
+                            .map_err(|err| BuildError::Io(target.clone(), err))?;
+                        let relative = relative.to_string_lossy().replace('\\', "/");
+                        let _ = writeln!(
+                            list,
+                            "    lua_shared::embed::EmbeddedFile {{ path: {:?}, chunk: include_bytes!({:?}) }},",
+                            relative,
+                            target.to_string_lossy()
+                        );
+                    }
+                    Err(message) => {
+                        println!("cargo:warning={}", message.replace('\n', " "));
+                        errors.push(CompileError { path, message });
+                    }
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(BuildError::Compile(errors));
+        }
+        list.push_str("])\n");
+        let list_path = out_dir.join(format!("{}.rs", name));
+        std::fs::write(&list_path, list).map_err(|err| BuildError::Io(list_path.clone(), err))?;
+        Ok(list_path)
+    }
+}
+
+/// Collects the `.lua` files under `directory`, relative to the root of the walk.
+fn collect(
+    directory: &Path,
+    relative: &Path,
+    files: &mut Vec<PathBuf>,
+) -> std::result::Result<(), BuildError> {
+    let path = directory.join(relative);
+    let entries = std::fs::read_dir(&path).map_err(|err| BuildError::Io(path.clone(), err))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| BuildError::Io(path.clone(), err))?;
+        let relative = relative.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .map_err(|err| BuildError::Io(entry.path(), err))?;
+        if file_type.is_dir() {
+            collect(directory, &relative, files)?;
+        } else if relative
+            .extension()
+            .is_some_and(|extension| extension == "lua")
+        {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Compiles `source`, stripping debug info if asked to.
+unsafe fn compile(
+    state: lua_State,
+    source: &[u8],
+    path: &Path,
+    strip: bool,
+) -> std::result::Result<Vec<u8>, String> {
+    let chunk_name = format!("@{}\0", path.display());
+    load_buffer(state, source, chunk_name.as_ptr(), LoadMode::Text).map_err(|err| match err {
+        LError::SyntaxError(message) | LError::MemoryError(message) => message,
+        err => format!("{}: {}", path.display(), err),
+    })?;
+    let mut bytecode = Vec::new();
+    let dumped = dump_strip(state, &mut bytecode, strip);
+    pop!(state, 1);
+    dumped.map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(bytecode)
+}
+
+/// Closes the state on drop, so early returns don't leak it.
+struct StateGuard(lua_State);
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        unsafe { close(self.0) };
+    }
+}
@@ -0,0 +1,803 @@
+//! LuaJIT bytecode parser and disassembler.
+//!
+//! Understands the dumps produced by [`dump`](crate::dump) (and `string.dump`) of LuaJIT 2.0 (dump version 1) and 2.1 (dump version 2).
+//! ```no_run
+//! # use lua_shared::bytecode;
+//! # fn audit(chunk: &[u8]) -> Result<(), lua_shared::LError> {
+//! let dump = bytecode::parse(chunk)?;
+//! println!("{}", dump);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::{self, Write};
+
+use crate::LError;
+
+pub const FLAG_BE: u32 = 0x01;
+pub const FLAG_STRIP: u32 = 0x02;
+pub const FLAG_FFI: u32 = 0x04;
+pub const FLAG_FR2: u32 = 0x08;
+
+pub const PROTO_CHILD: u8 = 0x01;
+pub const PROTO_VARARG: u8 = 0x02;
+pub const PROTO_FFI: u8 = 0x04;
+pub const PROTO_NOJIT: u8 = 0x08;
+pub const PROTO_ILOOP: u8 = 0x10;
+
+/// Operand kinds, as in `lj_bc.h`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Dst,
+    Base,
+    Var,
+    RBase,
+    Uv,
+    Lit,
+    Lits,
+    Pri,
+    Num,
+    Str,
+    Tab,
+    Func,
+    Jump,
+    CData,
+}
+
+/// Name and operand kinds (A, B, C/D) of an opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpInfo {
+    pub name: &'static str,
+    pub a: Operand,
+    pub b: Operand,
+    pub d: Operand,
+}
+
+macro_rules! opcodes {
+    ($table:ident; $($name:ident $a:ident $b:ident $d:ident),* $(,)?) => {
+        const $table: &[OpInfo] = &[
+            $(OpInfo { name: stringify!($name), a: Operand::$a, b: Operand::$b, d: Operand::$d }),*
+        ];
+    };
+}
+
+opcodes!(OPCODES_21;
+    ISLT Var None Var, ISGE Var None Var, ISLE Var None Var, ISGT Var None Var,
+    ISEQV Var None Var, ISNEV Var None Var, ISEQS Var None Str, ISNES Var None Str,
+    ISEQN Var None Num, ISNEN Var None Num, ISEQP Var None Pri, ISNEP Var None Pri,
+    ISTC Dst None Var, ISFC Dst None Var, IST None None Var, ISF None None Var,
+    ISTYPE Var None Lit, ISNUM Var None Lit,
+    MOV Dst None Var, NOT Dst None Var, UNM Dst None Var, LEN Dst None Var,
+    ADDVN Dst Var Num, SUBVN Dst Var Num, MULVN Dst Var Num, DIVVN Dst Var Num, MODVN Dst Var Num,
+    ADDNV Dst Var Num, SUBNV Dst Var Num, MULNV Dst Var Num, DIVNV Dst Var Num, MODNV Dst Var Num,
+    ADDVV Dst Var Var, SUBVV Dst Var Var, MULVV Dst Var Var, DIVVV Dst Var Var, MODVV Dst Var Var,
+    POW Dst Var Var, CAT Dst RBase RBase,
+    KSTR Dst None Str, KCDATA Dst None CData, KSHORT Dst None Lits, KNUM Dst None Num,
+    KPRI Dst None Pri, KNIL Base None Base,
+    UGET Dst None Uv, USETV Uv None Var, USETS Uv None Str, USETN Uv None Num, USETP Uv None Pri,
+    UCLO RBase None Jump, FNEW Dst None Func,
+    TNEW Dst None Lit, TDUP Dst None Tab, GGET Dst None Str, GSET Var None Str,
+    TGETV Dst Var Var, TGETS Dst Var Str, TGETB Dst Var Lit, TGETR Dst Var Var,
+    TSETV Var Var Var, TSETS Var Var Str, TSETB Var Var Lit, TSETM Base None Num, TSETR Var Var Var,
+    CALLM Base Lit Lit, CALL Base Lit Lit, CALLMT Base None Lit, CALLT Base None Lit,
+    ITERC Base Lit Lit, ITERN Base Lit Lit, VARG Base Lit Lit, ISNEXT Base None Jump,
+    RETM Base None Lit, RET RBase None Lit, RET0 RBase None Lit, RET1 RBase None Lit,
+    FORI Base None Jump, JFORI Base None Jump,
+    FORL Base None Jump, IFORL Base None Jump, JFORL Base None Lit,
+    ITERL Base None Jump, IITERL Base None Jump, JITERL Base None Lit,
+    LOOP RBase None Jump, ILOOP RBase None Jump, JLOOP RBase None Lit,
+    JMP RBase None Jump,
+    FUNCF RBase None None, IFUNCF RBase None None, JFUNCF RBase None Lit,
+    FUNCV RBase None None, IFUNCV RBase None None, JFUNCV RBase None Lit,
+    FUNCC RBase None None, FUNCCW RBase None None,
+);
+
+opcodes!(OPCODES_20;
+    ISLT Var None Var, ISGE Var None Var, ISLE Var None Var, ISGT Var None Var,
+    ISEQV Var None Var, ISNEV Var None Var, ISEQS Var None Str, ISNES Var None Str,
+    ISEQN Var None Num, ISNEN Var None Num, ISEQP Var None Pri, ISNEP Var None Pri,
+    ISTC Dst None Var, ISFC Dst None Var, IST None None Var, ISF None None Var,
+    MOV Dst None Var, NOT Dst None Var, UNM Dst None Var, LEN Dst None Var,
+    ADDVN Dst Var Num, SUBVN Dst Var Num, MULVN Dst Var Num, DIVVN Dst Var Num, MODVN Dst Var Num,
+    ADDNV Dst Var Num, SUBNV Dst Var Num, MULNV Dst Var Num, DIVNV Dst Var Num, MODNV Dst Var Num,
+    ADDVV Dst Var Var, SUBVV Dst Var Var, MULVV Dst Var Var, DIVVV Dst Var Var, MODVV Dst Var Var,
+    POW Dst Var Var, CAT Dst RBase RBase,
+    KSTR Dst None Str, KCDATA Dst None CData, KSHORT Dst None Lits, KNUM Dst None Num,
+    KPRI Dst None Pri, KNIL Base None Base,
+    UGET Dst None Uv, USETV Uv None Var, USETS Uv None Str, USETN Uv None Num, USETP Uv None Pri,
+    UCLO RBase None Jump, FNEW Dst None Func,
+    TNEW Dst None Lit, TDUP Dst None Tab, GGET Dst None Str, GSET Var None Str,
+    TGETV Dst Var Var, TGETS Dst Var Str, TGETB Dst Var Lit,
+    TSETV Var Var Var, TSETS Var Var Str, TSETB Var Var Lit, TSETM Base None Num,
+    CALLM Base Lit Lit, CALL Base Lit Lit, CALLMT Base None Lit, CALLT Base None Lit,
+    ITERC Base Lit Lit, ITERN Base Lit Lit, VARG Base Lit Lit, ISNEXT Base None Jump,
+    RETM Base None Lit, RET RBase None Lit, RET0 RBase None Lit, RET1 RBase None Lit,
+    FORI Base None Jump, JFORI Base None Jump,
+    FORL Base None Jump, IFORL Base None Jump, JFORL Base None Lit,
+    ITERL Base None Jump, IITERL Base None Jump, JITERL Base None Lit,
+    LOOP RBase None Jump, ILOOP RBase None Jump, JLOOP RBase None Lit,
+    JMP RBase None Jump,
+    FUNCF RBase None None, IFUNCF RBase None None, JFUNCF RBase None Lit,
+    FUNCV RBase None None, IFUNCV RBase None None, JFUNCV RBase None Lit,
+    FUNCC RBase None None, FUNCCW RBase None None,
+);
+
+/// Names of the internal variables used by `for` loops.
+const INTERNAL_VARIABLES: &[&str] = &[
+    "(for idx)",
+    "(for stop)",
+    "(for step)",
+    "(for gen)",
+    "(for state)",
+    "(for ctl)",
+];
+
+/// A single bytecode instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction(pub u32);
+
+impl Instruction {
+    pub fn op(self) -> u8 {
+        self.0 as u8
+    }
+
+    pub fn a(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn b(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn c(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn d(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TableConstant {
+    Nil,
+    False,
+    True,
+    Integer(i32),
+    Number(f64),
+    String(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GcConstant {
+    /// A nested function, as an index into [`Dump::prototypes`].
+    Child(usize),
+    Table {
+        array: Vec<TableConstant>,
+        hash: Vec<(TableConstant, TableConstant)>,
+    },
+    I64(i64),
+    U64(u64),
+    Complex(f64, f64),
+    String(Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberConstant {
+    Integer(i32),
+    Number(f64),
+}
+
+impl NumberConstant {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            NumberConstant::Integer(value) => value as f64,
+            NumberConstant::Number(value) => value,
+        }
+    }
+}
+
+/// Debug information about a local variable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub name: Vec<u8>,
+    pub start_pc: u32,
+    pub end_pc: u32,
+}
+
+/// A function prototype.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prototype {
+    pub flags: u8,
+    pub params: u8,
+    pub frame_size: u8,
+    /// Instructions, without the implicit function header.
+    pub instructions: Vec<Instruction>,
+    pub upvalues: Vec<u16>,
+    /// GC constants in dump order. Operand `d` refers to `kgc[kgc.len() - 1 - d]`.
+    pub kgc: Vec<GcConstant>,
+    pub knum: Vec<NumberConstant>,
+    pub first_line: u32,
+    pub num_lines: u32,
+    /// Absolute line of every instruction. Empty for stripped dumps.
+    pub line_info: Vec<u32>,
+    pub upvalue_names: Vec<Vec<u8>>,
+    pub variables: Vec<Variable>,
+}
+
+impl Prototype {
+    /// Returns the GC constant referred to by operand `d`.
+    pub fn gc_constant(&self, d: u16) -> Option<&GcConstant> {
+        self.kgc
+            .len()
+            .checked_sub(d as usize + 1)
+            .and_then(|index| self.kgc.get(index))
+    }
+
+    pub fn last_line(&self) -> u32 {
+        self.first_line.saturating_add(self.num_lines)
+    }
+}
+
+/// A parsed bytecode dump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dump {
+    pub version: u8,
+    pub flags: u32,
+    /// `None` for stripped dumps.
+    pub chunk_name: Option<Vec<u8>>,
+    /// Prototypes in dump order: children come before their parents, and the main chunk is the last one.
+    pub prototypes: Vec<Prototype>,
+}
+
+impl Dump {
+    pub fn is_stripped(&self) -> bool {
+        self.flags & FLAG_STRIP != 0
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.flags & FLAG_BE != 0
+    }
+
+    pub fn uses_ffi(&self) -> bool {
+        self.flags & FLAG_FFI != 0
+    }
+
+    /// Returns the opcode table for the dump version.
+    pub fn opcodes(&self) -> &'static [OpInfo] {
+        match self.version {
+            1 => OPCODES_20,
+            _ => OPCODES_21,
+        }
+    }
+
+    /// Returns the main chunk.
+    pub fn main(&self) -> Option<&Prototype> {
+        self.prototypes.last()
+    }
+
+    /// Writes a listing in the format of `jit.bcsave -l`.
+    pub fn disassemble<WRITER>(&self, out: &mut WRITER) -> fmt::Result
+    where
+        WRITER: Write,
+    {
+        match self.prototypes.len() {
+            0 => Ok(()),
+            len => self.disassemble_prototype(out, len - 1),
+        }
+    }
+
+    fn disassemble_prototype<WRITER>(&self, out: &mut WRITER, index: usize) -> fmt::Result
+    where
+        WRITER: Write,
+    {
+        let proto = &self.prototypes[index];
+        if proto.flags & PROTO_CHILD != 0 {
+            for constant in proto.kgc.iter().rev() {
+                if let GcConstant::Child(child) = constant {
+                    self.disassemble_prototype(out, *child)?;
+                }
+            }
+        }
+        writeln!(
+            out,
+            "-- BYTECODE -- {}-{}",
+            self.location(proto),
+            proto.last_line()
+        )?;
+        let opcodes = self.opcodes();
+        let mut targets = vec![false; proto.instructions.len() + 2];
+        for (pc, ins) in (1..).zip(&proto.instructions) {
+            if opcodes.get(ins.op() as usize).map(|info| info.d) == Some(Operand::Jump) {
+                let target = pc + ins.d() as i64 - 0x7fff;
+                if let Some(target) = targets.get_mut(target as usize) {
+                    *target = true;
+                }
+            }
+        }
+        for (pc, ins) in (1..).zip(&proto.instructions) {
+            self.disassemble_instruction(out, proto, pc, *ins, targets[pc as usize])?;
+        }
+        writeln!(out)
+    }
+
+    fn disassemble_instruction<WRITER>(
+        &self,
+        out: &mut WRITER,
+        proto: &Prototype,
+        pc: i64,
+        ins: Instruction,
+        target: bool,
+    ) -> fmt::Result
+    where
+        WRITER: Write,
+    {
+        let prefix = if target { "=>" } else { "  " };
+        let Some(info) = self.opcodes().get(ins.op() as usize) else {
+            return writeln!(out, "{:04} {} ??? 0x{:08x}", pc, prefix, ins.0);
+        };
+        let a = if info.a == Operand::None {
+            String::new()
+        } else {
+            ins.a().to_string()
+        };
+        write!(out, "{:04} {} {:<6} {:>3} ", pc, prefix, info.name, a)?;
+        if info.d == Operand::Jump {
+            return writeln!(out, "=> {:04}", pc + ins.d() as i64 - 0x7fff);
+        }
+        let d = if info.b != Operand::None {
+            ins.c() as u16
+        } else if info.d == Operand::None {
+            return writeln!(out);
+        } else {
+            ins.d()
+        };
+        let mut comment = match info.d {
+            Operand::Str => proto.gc_constant(d).map(|constant| match constant {
+                GcConstant::String(string) => quote(string),
+                _ => String::from("?"),
+            }),
+            Operand::Num => proto.knum.get(d as usize).map(|constant| {
+                let mut value = constant.as_f64();
+                if info.name == "TSETM" {
+                    value -= 2f64.powi(52);
+                }
+                format_number(value)
+            }),
+            Operand::Func => proto.gc_constant(d).map(|constant| match constant {
+                GcConstant::Child(child) => self.location(&self.prototypes[*child]),
+                _ => String::from("?"),
+            }),
+            Operand::Uv => Some(upvalue_name(proto, d)),
+            _ => None,
+        };
+        if info.a == Operand::Uv {
+            let name = upvalue_name(proto, ins.a() as u16);
+            comment = Some(match comment {
+                Some(comment) => format!("{} ; {}", name, comment),
+                None => name,
+            });
+        }
+        match (info.b, comment) {
+            (Operand::None, Some(comment)) => writeln!(out, "{:>3}      ; {}", d, comment),
+            (Operand::None, None) if info.d == Operand::Lits => {
+                writeln!(out, "{:>3}", d as i16)
+            }
+            (Operand::None, None) => writeln!(out, "{:>3}", d),
+            (_, Some(comment)) => writeln!(out, "{:>3} {:>3}  ; {}", ins.b(), d, comment),
+            (_, None) => writeln!(out, "{:>3} {:>3}", ins.b(), d),
+        }
+    }
+
+    fn location(&self, proto: &Prototype) -> String {
+        let name = match &self.chunk_name {
+            Some(name) => match name.first() {
+                Some(b'@') | Some(b'=') => String::from_utf8_lossy(&name[1..]).into_owned(),
+                _ => format!(
+                    "[string \"{}\"]",
+                    String::from_utf8_lossy(name.split(|&byte| byte == b'\n').next().unwrap())
+                ),
+            },
+            None => String::from("?"),
+        };
+        format!("{}:{}", name, proto.first_line)
+    }
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.disassemble(f)
+    }
+}
+
+fn upvalue_name(proto: &Prototype, index: u16) -> String {
+    match proto.upvalue_names.get(index as usize) {
+        Some(name) => String::from_utf8_lossy(name).into_owned(),
+        None => index.to_string(),
+    }
+}
+
+/// Quotes a string constant the way `jit.bc` does.
+fn quote(string: &[u8]) -> String {
+    let mut quoted = String::new();
+    for &byte in string {
+        match byte {
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0..=31 | 127 => {
+                let _ = write!(quoted, "\\{:03}", byte);
+            }
+            _ => quoted.push(byte as char),
+        }
+    }
+    if string.len() > 40 {
+        format!("\"{}\"~", quoted.chars().take(40).collect::<String>())
+    } else {
+        format!("\"{}\"", quoted)
+    }
+}
+
+/// Formats a number like Lua's `tostring` (`%.14g`).
+pub(crate) fn format_number(value: f64) -> String {
+    if value.is_nan() {
+        return String::from("nan");
+    }
+    if value.is_infinite() {
+        return String::from(if value > 0.0 { "inf" } else { "-inf" });
+    }
+    if value == 0.0 {
+        return String::from(if value.is_sign_negative() { "-0" } else { "0" });
+    }
+    let scientific = format!("{:.13e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if !(-4..14).contains(&exponent) {
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else {
+        let fixed = format!("{:.*}", (13 - exponent) as usize, value);
+        if fixed.contains('.') {
+            fixed
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        } else {
+            fixed
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> LError {
+        LError::BytecodeError(format!("{} at offset {}", message, self.position))
+    }
+
+    fn bytes(&mut self, len: usize) -> std::result::Result<&'a [u8], LError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| self.error("unexpected end of dump"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// Checks that `count` items of at least `size` bytes each can still be read, before reserving room for them.
+    fn count(&self, count: u32, size: usize) -> std::result::Result<usize, LError> {
+        let count = count as usize;
+        match count.checked_mul(size) {
+            Some(len) if len <= self.data.len() - self.position => Ok(count),
+            _ => Err(self.error("count larger than the dump")),
+        }
+    }
+
+    fn byte(&mut self) -> std::result::Result<u8, LError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, LError> {
+        let bytes = self.bytes(2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, LError> {
+        let bytes = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn uleb128(&mut self) -> std::result::Result<u32, LError> {
+        let mut value = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 32 {
+                value |= ((byte & 0x7f) as u32) << shift;
+            }
+            if byte < 0x80 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 35 {
+                return Err(self.error("malformed uleb128"));
+            }
+        }
+    }
+
+    /// Reads the top 32 bits of a 33 bit uleb128, the lowest bit is returned separately.
+    fn uleb128_33(&mut self) -> std::result::Result<(bool, u32), LError> {
+        let first = self.byte()?;
+        let mut value = (first >> 1) as u32;
+        if value >= 0x40 {
+            value &= 0x3f;
+            let mut shift = 6;
+            loop {
+                let byte = self.byte()?;
+                if shift < 32 {
+                    value |= ((byte & 0x7f) as u32) << shift;
+                }
+                if byte < 0x80 {
+                    break;
+                }
+                shift += 7;
+                if shift > 35 {
+                    return Err(self.error("malformed uleb128"));
+                }
+            }
+        }
+        Ok((first & 1 != 0, value))
+    }
+
+    fn table_constant(&mut self) -> std::result::Result<TableConstant, LError> {
+        Ok(match self.uleb128()? {
+            0 => TableConstant::Nil,
+            1 => TableConstant::False,
+            2 => TableConstant::True,
+            3 => TableConstant::Integer(self.uleb128()? as i32),
+            4 => {
+                let lo = self.uleb128()? as u64;
+                let hi = self.uleb128()? as u64;
+                TableConstant::Number(f64::from_bits(hi << 32 | lo))
+            }
+            kind => TableConstant::String(self.bytes(kind as usize - 5)?.to_vec()),
+        })
+    }
+
+    fn u64(&mut self) -> std::result::Result<u64, LError> {
+        let lo = self.uleb128()? as u64;
+        let hi = self.uleb128()? as u64;
+        Ok(hi << 32 | lo)
+    }
+}
+
+fn parse_prototype(
+    reader: &mut Reader,
+    stripped: bool,
+    children: &mut Vec<usize>,
+) -> std::result::Result<Prototype, LError> {
+    let flags = reader.byte()?;
+    let params = reader.byte()?;
+    let frame_size = reader.byte()?;
+    let size_uv = reader.byte()? as usize;
+    let size_kgc = reader.uleb128()?;
+    let size_kn = reader.uleb128()?;
+    let size_bc = reader.uleb128()?;
+    let (mut size_dbg, mut first_line, mut num_lines) = (0, 0, 0);
+    if !stripped {
+        size_dbg = reader.uleb128()? as usize;
+        if size_dbg > 0 {
+            first_line = reader.uleb128()?;
+            num_lines = reader.uleb128()?;
+            if first_line.checked_add(num_lines).is_none() {
+                return Err(reader.error("line range overflows"));
+            }
+        }
+    }
+    let size_bc = reader.count(size_bc, 4)?;
+    let instructions = (0..size_bc)
+        .map(|_| reader.u32().map(Instruction))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let upvalues = (0..size_uv)
+        .map(|_| reader.u16())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let size_kgc = reader.count(size_kgc, 1)?;
+    let mut kgc = Vec::with_capacity(size_kgc);
+    for _ in 0..size_kgc {
+        kgc.push(match reader.uleb128()? {
+            0 => GcConstant::Child(
+                children
+                    .pop()
+                    .ok_or_else(|| reader.error("child prototype missing"))?,
+            ),
+            1 => {
+                let narray = reader.uleb128()?;
+                let nhash = reader.uleb128()?;
+                let narray = reader.count(narray, 1)?;
+                let nhash = reader.count(nhash, 2)?;
+                let array = (0..narray)
+                    .map(|_| reader.table_constant())
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let hash = (0..nhash)
+                    .map(|_| Ok((reader.table_constant()?, reader.table_constant()?)))
+                    .collect::<std::result::Result<Vec<_>, LError>>()?;
+                GcConstant::Table { array, hash }
+            }
+            2 => GcConstant::I64(reader.u64()? as i64),
+            3 => GcConstant::U64(reader.u64()?),
+            4 => GcConstant::Complex(f64::from_bits(reader.u64()?), f64::from_bits(reader.u64()?)),
+            kind => GcConstant::String(reader.bytes(kind as usize - 5)?.to_vec()),
+        });
+    }
+    let size_kn = reader.count(size_kn, 1)?;
+    let mut knum = Vec::with_capacity(size_kn);
+    for _ in 0..size_kn {
+        let (is_number, lo) = reader.uleb128_33()?;
+        knum.push(if is_number {
+            let hi = reader.uleb128()? as u64;
+            NumberConstant::Number(f64::from_bits(hi << 32 | lo as u64))
+        } else {
+            NumberConstant::Integer(lo as i32)
+        });
+    }
+    let mut proto = Prototype {
+        flags,
+        params,
+        frame_size,
+        instructions,
+        upvalues,
+        kgc,
+        knum,
+        first_line,
+        num_lines,
+        line_info: Vec::new(),
+        upvalue_names: Vec::new(),
+        variables: Vec::new(),
+    };
+    if size_dbg > 0 {
+        let debug = reader.bytes(size_dbg)?;
+        parse_debug(reader, &mut proto, debug)?;
+    }
+    Ok(proto)
+}
+
+fn parse_debug(
+    outer: &Reader,
+    proto: &mut Prototype,
+    debug: &[u8],
+) -> std::result::Result<(), LError> {
+    let mut reader = Reader {
+        data: debug,
+        position: 0,
+        big_endian: outer.big_endian,
+    };
+    for _ in 0..proto.instructions.len() {
+        let offset = match proto.num_lines {
+            0..=255 => reader.byte()? as u32,
+            256..=65535 => reader.u16()? as u32,
+            _ => reader.u32()?,
+        };
+        let line = proto
+            .first_line
+            .checked_add(offset)
+            .ok_or_else(|| outer.error("line number overflows"))?;
+        proto.line_info.push(line);
+    }
+    for _ in 0..proto.upvalues.len() {
+        proto.upvalue_names.push(cstring(&mut reader)?);
+    }
+    let mut last_pc = 0;
+    loop {
+        let kind = *debug
+            .get(reader.position)
+            .ok_or_else(|| outer.error("truncated variable info"))?;
+        let name = match kind {
+            0 => break,
+            1..=6 => {
+                reader.position += 1;
+                INTERNAL_VARIABLES[kind as usize - 1].as_bytes().to_vec()
+            }
+            _ => cstring(&mut reader)?,
+        };
+        let start_pc = checked_pc(outer, last_pc, reader.uleb128()?)?;
+        last_pc = start_pc;
+        let end_pc = checked_pc(outer, start_pc, reader.uleb128()?)?;
+        proto.variables.push(Variable {
+            name,
+            start_pc,
+            end_pc,
+        });
+    }
+    Ok(())
+}
+
+fn checked_pc(reader: &Reader, pc: u32, offset: u32) -> std::result::Result<u32, LError> {
+    pc.checked_add(offset)
+        .ok_or_else(|| reader.error("variable range overflows"))
+}
+
+fn cstring(reader: &mut Reader) -> std::result::Result<Vec<u8>, LError> {
+    let rest = &reader.data[reader.position..];
+    let len = rest
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| reader.error("unterminated string"))?;
+    reader.position += len + 1;
+    Ok(rest[..len].to_vec())
+}
+
+/// Returns `true` if `data` starts with the LuaJIT bytecode signature.
+pub fn is_bytecode(data: &[u8]) -> bool {
+    data.starts_with(b"\x1bLJ")
+}
+
+/// Parses a LuaJIT bytecode dump.
+pub fn parse(data: &[u8]) -> std::result::Result<Dump, LError> {
+    let mut reader = Reader {
+        data,
+        position: 0,
+        big_endian: false,
+    };
+    if reader.bytes(3).ok() != Some(b"\x1bLJ".as_slice()) {
+        return Err(LError::BytecodeError(String::from(
+            "not a LuaJIT bytecode dump",
+        )));
+    }
+    let version = reader.byte()?;
+    if !(1..=2).contains(&version) {
+        return Err(reader.error(&format!("unsupported dump version {}", version)));
+    }
+    let flags = reader.uleb128()?;
+    reader.big_endian = flags & FLAG_BE != 0;
+    let stripped = flags & FLAG_STRIP != 0;
+    let chunk_name = if stripped {
+        None
+    } else {
+        let len = reader.uleb128()? as usize;
+        Some(reader.bytes(len)?.to_vec())
+    };
+    let mut prototypes = Vec::new();
+    let mut children = Vec::new();
+    loop {
+        let len = match reader.uleb128() {
+            Ok(0) => break,
+            Ok(len) => len as usize,
+            // The terminating zero is optional.
+            Err(_) if reader.position >= data.len() => break,
+            Err(err) => return Err(err),
+        };
+        let start = reader.position;
+        let proto = parse_prototype(&mut reader, stripped, &mut children)?;
+        if reader.position - start != len {
+            return Err(reader.error("prototype length mismatch"));
+        }
+        children.push(prototypes.len());
+        prototypes.push(proto);
+    }
+    Ok(Dump {
+        version,
+        flags,
+        chunk_name,
+        prototypes,
+    })
+}
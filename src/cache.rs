@@ -0,0 +1,140 @@
+use std::{
+    ffi::CString,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cstr, dump, exec::load_buffer, get_type, getfield, lua_State, pop, sha256, tolstring, LError,
+    LoadMode, GLOBALSINDEX, TSTRING, TTABLE,
+};
+
+/// On-disk cache of compiled chunks.
+///
+/// Entries are keyed by the SHA-256 of the LuaJIT version (`jit.version`), the chunk name and the source, so a game update that changes LuaJIT never picks up stale bytecode.
+/// Cached entries are loaded as binary chunks, so the directory must not be writable by anyone you don't trust.
+/// ```no_run
+/// # use lua_shared::{self as lua, BytecodeCache};
+/// # unsafe fn run(state: lua::lua_State, source: &[u8]) -> Result<(), lua::LError> {
+/// let cache = BytecodeCache::new("garrysmod/cache/lua-shared");
+/// cache.load(state, source, "@addons/foo/lua/autorun/foo.lua")?;
+/// lua::call(state, 0, 0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct BytecodeCache {
+    directory: PathBuf,
+}
+
+impl BytecodeCache {
+    pub fn new<PATH>(directory: PATH) -> Self
+    where
+        PATH: Into<PathBuf>,
+    {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the path of the cache entry for `source` loaded as `chunk_name` in `state`.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state with room for two more stack slots, to look up `jit.version`.
+    pub unsafe fn entry_path(&self, state: lua_State, source: &[u8], chunk_name: &str) -> PathBuf {
+        let mut key = version(state);
+        key.push(0);
+        key.extend_from_slice(chunk_name.as_bytes());
+        key.push(0);
+        key.extend_from_slice(source);
+        let mut name = String::with_capacity(64 + 5);
+        for byte in sha256(&key) {
+            let _ = write!(name, "{:02x}", byte);
+        }
+        name.push_str(".ljbc");
+        self.directory.join(name)
+    }
+
+    /// Loads `source` as a Lua chunk, using the cached bytecode if there is any.
+    ///
+    /// On a cache miss the source is compiled and its dump is written to the cache. Cache entries that can't be read or loaded are ignored and overwritten, and failing to write the cache is not an error.
+    /// On success the loaded function is pushed onto the stack.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state with room for a few more stack slots.
+    pub unsafe fn load(
+        &self,
+        state: lua_State,
+        source: &[u8],
+        chunk_name: &str,
+    ) -> std::result::Result<(), LError> {
+        let name = CString::new(chunk_name)
+            .map_err(|err| LError::Rejected(format!("invalid chunk name: {}", err)))?;
+        let path = self.entry_path(state, source, chunk_name);
+        if let Ok(bytecode) = std::fs::read(&path) {
+            if load_buffer(state, &bytecode, name.as_ptr().cast(), LoadMode::Binary).is_ok() {
+                return Ok(());
+            }
+        }
+        load_buffer(state, source, name.as_ptr().cast(), LoadMode::Text)?;
+        let mut bytecode = Vec::new();
+        if dump(state, &mut bytecode).is_ok() {
+            let _ = self.store(&path, &bytecode);
+        }
+        Ok(())
+    }
+
+    fn store(&self, path: &Path, bytecode: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let temporary = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&temporary, bytecode)?;
+        std::fs::rename(&temporary, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temporary);
+        })
+    }
+
+    /// Removes every entry from the cache.
+    pub fn clear(&self) -> std::io::Result<()> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "ljbc")
+            {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns `jit.version`, or an empty string if the `jit` library isn't loaded.
+unsafe fn version(state: lua_State) -> Vec<u8> {
+    let mut version = Vec::new();
+    getfield(state, GLOBALSINDEX, cstr!("jit"));
+    if get_type(state, -1) == TTABLE {
+        getfield(state, -1, cstr!("version"));
+        if get_type(state, -1) == TSTRING {
+            let mut len = 0;
+            let ptr = tolstring(state, -1, &mut len);
+            version.extend_from_slice(std::slice::from_raw_parts(ptr, len));
+        }
+        pop!(state, 1);
+    }
+    pop!(state, 1);
+    version.extend_from_slice(if cfg!(target_pointer_width = "64") {
+        b"/64"
+    } else {
+        b"/32"
+    });
+    version
+}
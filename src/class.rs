@@ -0,0 +1,471 @@
+//! Lua classes backed by Rust types.
+//!
+//! [`Class`] defines a class table with a constructor, methods and static members. Calling the class table creates an instance: a plain table whose metatable is the class. The Rust value is kept in a userdata stored next to it, where `pairs` doesn't reach it.
+//! Classes inherit through `__index` chains, so Lua code can subclass them and override methods, and Rust reaches the overrides with [`call_method`]:
+//! ```no_run
+//! # use lua_shared::{self as lua, class::{self, Class}, cstr};
+//! struct Entity {
+//!     health: f64,
+//! }
+//!
+//! # unsafe fn open(state: lua::lua_State) -> Result<(), lua::LError> {
+//! Class::new("Entity", |_| Ok(Entity { health: 100.0 }))
+//!     .method("Damage", |state, this: &mut Entity| {
+//!         this.health -= lua::Lchecknumber(state, 2);
+//!         Ok(0)
+//!     })
+//!     .method("Think", |_, _| Ok(0))
+//!     .register(state)?;
+//! lua::setglobal!(state, cstr!("Entity"));
+//!
+//! let _: () = lua::exec(state, r#"
+//!     Zombie = Entity:extend("Zombie")
+//!     function Zombie:init(name) self.name = name end
+//!     function Zombie:Think() self:Damage(1) end
+//!     zombie = Zombie("bob")
+//! "#, "example")?;
+//!
+//! lua::getglobal!(state, cstr!("zombie"));
+//! let _: () = class::call_method(state, -1, "Think", 0)?; // runs Zombie:Think
+//! assert_eq!(class::get::<Entity>(state, -1).unwrap().health, 99.0);
+//! lua::pop!(state, 1);
+//! # Ok(())
+//! # }
+//! ```
+//! Subclasses are made with `Base:extend(name)`. Calling a class runs the Rust constructors of every class in the chain, base first, with the call's arguments, then the `init` method if there is one.
+//! Each class table has `BaseClass` pointing to its parent, for calling overridden methods: `Zombie.BaseClass.Think(self)`. Metamethods (`__tostring`, `__eq`, ...) are copied to subclasses when they are created.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    error::Error,
+};
+
+use crate::{
+    absindex, createtable, cstr,
+    exec::{call, load_buffer},
+    get_type, getfield, getmetatable, gettop, insert, lua_State, newuserdata, pcall,
+    pcall_callback, pop, pop_message, pushboolean, pushcclosure, pushfunction, pushlightuserdata,
+    pushlstring, pushnil, pushvalue, rawequal, rawget, rawset, setfield, setmetatable, settop,
+    touserdata, type_key, FromLua, LError, LoadMode, Result, Status, REGISTRYINDEX, TTABLE,
+    TUSERDATA,
+};
+
+/// Registry field holding the class runtime and the registered classes.
+const REGISTRY_FIELD: *const u8 = cstr!("lua_shared.class");
+
+/// Key of the Rust constructor in class tables.
+static CONSTRUCTOR_KEY: u8 = 0;
+
+const RUNTIME: &str = r#"
+local CONSTRUCTOR = ...
+local pairs, rawget, rawset, setmetatable, type =
+    pairs, rawget, rawset, setmetatable, type
+
+local setup
+
+-- Instance data by data key, in weak-keyed tables so `pairs` over an instance doesn't reach it.
+local storage = {}
+local weak = { __mode = "k" }
+
+local function construct(class, ...)
+    local object = setmetatable({}, class)
+    local constructors = {}
+    local current = class
+    while current do
+        constructors[#constructors + 1] = rawget(current, CONSTRUCTOR)
+        current = rawget(current, "BaseClass")
+    end
+    for index = #constructors, 1, -1 do
+        local key, data = constructors[index](...)
+        local store = storage[key]
+        if not store then
+            store = setmetatable({}, weak)
+            storage[key] = store
+        end
+        store[object] = data
+    end
+    local init = object.init
+    if type(init) == "function" then
+        init(object, ...)
+    end
+    return object
+end
+
+local function extend(base, name)
+    local class = {}
+    setup(class, base, name)
+    return class
+end
+
+local private = { __index = true, __name = true }
+
+function setup(class, base, name)
+    class.__index = class
+    class.__name = name
+    class.BaseClass = base
+    if base then
+        for key, value in pairs(base) do
+            if type(key) == "string" and key:sub(1, 2) == "__" and not private[key] and rawget(class, key) == nil then
+                rawset(class, key, value)
+            end
+        end
+    else
+        class.extend = extend
+    end
+    return setmetatable(class, { __index = base, __call = construct })
+end
+
+local function invoke(object, name, ...)
+    local method = object[name]
+    if method == nil then
+        error("no method '" .. tostring(name) .. "' on " .. tostring(object), 2)
+    end
+    return method(object, ...)
+end
+
+return { setup = setup, invoke = invoke, classes = {}, storage = storage }
+"#;
+
+/// Pushes the runtime table, loading it first if needed.
+unsafe fn push_runtime(state: lua_State) -> std::result::Result<(), LError> {
+    getfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+    if get_type(state, -1) == TTABLE {
+        return Ok(());
+    }
+    pop!(state, 1);
+    load_buffer(
+        state,
+        RUNTIME.as_bytes(),
+        cstr!("=lua_shared.class"),
+        LoadMode::Text,
+    )?;
+    pushlightuserdata(state, &CONSTRUCTOR_KEY as *const u8 as _);
+    if pcall(state, 1, 1, 0) != Status::Ok {
+        return Err(LError::RuntimeMessage(pop_message(state)));
+    }
+    pushvalue(state, -1);
+    setfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+    Ok(())
+}
+
+type Method<T> = Box<dyn FnMut(lua_State, &mut T) -> Result>;
+
+enum Member<T> {
+    Method(Method<T>),
+    Function(Box<dyn FnMut(lua_State) -> Result>),
+    Value(Box<dyn FnOnce(lua_State)>),
+}
+
+type Constructor<T> = Box<dyn FnMut(lua_State) -> std::result::Result<T, Box<dyn Error>>>;
+
+/// What the data userdata of an instance holds.
+struct Data<T> {
+    /// Set while a method runs, so a method reaching the same instance again doesn't get a second `&mut T`.
+    borrowed: Cell<bool>,
+    value: UnsafeCell<T>,
+}
+
+/// Builder for a class whose instances hold a `T`.
+pub struct Class<T> {
+    name: String,
+    base: Option<String>,
+    constructor: Constructor<T>,
+    members: Vec<(String, Member<T>)>,
+}
+
+impl<T> Class<T>
+where
+    T: 'static,
+{
+    /// Starts a class called `name`. `constructor` gets the arguments of the call that creates the instance, starting at index 1.
+    ///
+    /// Panics if `T` needs more than 8-byte alignment, which is all Lua guarantees for userdata.
+    pub fn new<FUNC>(name: &str, constructor: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State) -> std::result::Result<T, Box<dyn Error>>,
+    {
+        assert!(
+            std::mem::align_of::<Data<T>>() <= 8,
+            "{} needs more alignment than Lua userdata has",
+            std::any::type_name::<T>()
+        );
+        Self {
+            name: name.to_string(),
+            base: None,
+            constructor: Box::new(constructor),
+            members: Vec::new(),
+        }
+    }
+
+    /// Inherits from the class registered as `base`. Instances also hold the base's Rust value, built by its constructor.
+    pub fn extends(mut self, base: &str) -> Self {
+        self.base = Some(base.to_string());
+        self
+    }
+
+    fn add(mut self, name: &str, member: Member<T>) -> Self {
+        self.members.retain(|(other, _)| other != name);
+        self.members.push((name.to_string(), member));
+        self
+    }
+
+    /// Adds a method, called as `object:name(...)`. The instance is at index 1 and its arguments start at index 2.
+    ///
+    /// Raises an error when called on something that is not an instance of this class or of a subclass, or on an instance whose `T` is already in use by a method further up the call stack.
+    pub fn method<FUNC>(self, name: &str, method: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State, &mut T) -> Result,
+    {
+        self.add(name, Member::Method(Box::new(method)))
+    }
+
+    /// Adds a static function, called as `Class.name(...)`. See [`pushfunction`].
+    pub fn function<FUNC>(self, name: &str, function: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State) -> Result,
+    {
+        self.add(name, Member::Function(Box::new(function)))
+    }
+
+    /// Adds the value pushed by `push` as the static member `name`. `push` must push exactly one value.
+    pub fn value<FUNC>(self, name: &str, push: FUNC) -> Self
+    where
+        FUNC: 'static + FnOnce(lua_State),
+    {
+        self.add(name, Member::Value(Box::new(push)))
+    }
+
+    /// Creates the class table, registers it under its name and pushes it onto the stack.
+    ///
+    /// Fails if the base class is not registered in `state`.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread.
+    pub unsafe fn register(self, state: lua_State) -> std::result::Result<(), LError> {
+        push_runtime(state)?;
+        let runtime = gettop(state);
+        getfield(state, runtime, cstr!("classes"));
+        let classes = gettop(state);
+        match &self.base {
+            Some(base) => {
+                pushlstring(state, base.as_ptr(), base.len());
+                rawget(state, classes);
+                if get_type(state, -1) != TTABLE {
+                    settop(state, runtime - 1);
+                    return Err(LError::RuntimeMessage(format!(
+                        "base class '{}' of '{}' is not registered",
+                        base, self.name
+                    )));
+                }
+            }
+            None => pushnil(state),
+        }
+        let base = gettop(state);
+
+        createtable(state, 0, self.members.len() as i32 + 4);
+        let class = gettop(state);
+        let class_name = self.name.clone();
+        for (name, member) in self.members {
+            pushlstring(state, name.as_ptr(), name.len());
+            match member {
+                Member::Method(mut method) => {
+                    let class_name = class_name.clone();
+                    pushfunction(state, move |state| match data::<T>(state, 1) {
+                        Some(data) if data.borrowed.get() => Err(format!(
+                            "cannot call '{}', the {} is already in use by another method",
+                            name, class_name
+                        )
+                        .into()),
+                        Some(data) => {
+                            data.borrowed.set(true);
+                            // Lua errors raised by the method are caught, so the flag is cleared before they are passed on.
+                            let result = pcall_callback(state, &mut |state| {
+                                method(state, &mut *data.value.get())
+                            });
+                            data.borrowed.set(false);
+                            match result {
+                                Some(nrets) => Ok(nrets),
+                                None => Err(pop_message(state).into()),
+                            }
+                        }
+                        None => Err(format!(
+                            "bad self to '{}' (instance of {} expected)",
+                            name, class_name
+                        )
+                        .into()),
+                    })
+                }
+                Member::Function(function) => pushfunction(state, function),
+                Member::Value(push) => push(state),
+            }
+            rawset(state, class);
+        }
+        pushlightuserdata(state, &CONSTRUCTOR_KEY as *const u8 as _);
+        push_constructor(state, self.constructor);
+        rawset(state, class);
+
+        getfield(state, runtime, cstr!("setup"));
+        pushvalue(state, class);
+        pushvalue(state, base);
+        pushlstring(state, class_name.as_ptr(), class_name.len());
+        if let Err(err) = call::<()>(state, 3) {
+            settop(state, runtime - 1);
+            return Err(err);
+        }
+        pushlstring(state, class_name.as_ptr(), class_name.len());
+        pushvalue(state, class);
+        rawset(state, classes);
+        // Leave only the class.
+        insert(state, runtime);
+        settop(state, runtime);
+        Ok(())
+    }
+}
+
+/// Pushes the function that builds the `T` of an instance. It returns the data key and a userdata holding the value.
+unsafe fn push_constructor<T>(state: lua_State, mut constructor: Constructor<T>)
+where
+    T: 'static,
+{
+    unsafe extern "C" fn drop_data<T>(state: lua_State) -> i32
+    where
+        T: 'static,
+    {
+        // `__gc` can also be reached through the debug library, with anything as its argument.
+        let data = touserdata(state, 1).cast::<Data<T>>();
+        if is_data::<T>(state, 1) && !(*data).borrowed.get() {
+            // Without its metatable the userdata is neither dropped again nor accepted by `data`.
+            pushnil(state);
+            setmetatable(state, 1);
+            data.drop_in_place();
+        }
+        0
+    }
+
+    pushfunction(state, move |state| {
+        let data = constructor(state)?;
+        let key = type_key::<T>();
+        pushlightuserdata(state, key);
+        newuserdata(state, std::mem::size_of::<Data<T>>())
+            .cast::<Data<T>>()
+            .write(Data {
+                borrowed: Cell::new(false),
+                value: UnsafeCell::new(data),
+            });
+        // One metatable per type, kept in the registry under the data key.
+        pushlightuserdata(state, key);
+        rawget(state, REGISTRYINDEX);
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 1);
+            createtable(state, 0, 2);
+            pushcclosure(state, drop_data::<T>, 0);
+            setfield(state, -2, cstr!("__gc"));
+            pushboolean(state, 0);
+            setfield(state, -2, cstr!("__metatable"));
+            pushlightuserdata(state, key);
+            pushvalue(state, -2);
+            rawset(state, REGISTRYINDEX);
+        }
+        setmetatable(state, -2);
+        Ok(2)
+    });
+}
+
+/// Pushes the class registered as `name`, or `nil`.
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for three more stack slots.
+pub unsafe fn push_class(state: lua_State, name: &str) -> std::result::Result<(), LError> {
+    push_runtime(state)?;
+    getfield(state, -1, cstr!("classes"));
+    pushlstring(state, name.as_ptr(), name.len());
+    rawget(state, -2);
+    crate::remove(state, -2);
+    crate::remove(state, -2);
+    Ok(())
+}
+
+/// Returns the `T` held by the instance at `index`, or `None` if it is not an instance of a class (or subclass) built from `T`, or if a method of the instance is running.
+///
+/// The reference points into a Lua userdata: it must not outlive the instance, and must not be held while Lua code that can reach the same instance runs.
+///
+/// # Safety
+/// Besides the above, `index` must be valid in `state`.
+pub unsafe fn get<'a, T>(state: lua_State, index: i32) -> Option<&'a mut T>
+where
+    T: 'static,
+{
+    match data::<T>(state, index) {
+        Some(data) if !data.borrowed.get() => Some(&mut *data.value.get()),
+        _ => None,
+    }
+}
+
+/// Returns the data of the instance at `index`, checking that the userdata stored for it under the key of `T` really was made by `T`'s constructor.
+unsafe fn data<'a, T>(state: lua_State, index: i32) -> Option<&'a Data<T>>
+where
+    T: 'static,
+{
+    if get_type(state, index) != TTABLE {
+        return None;
+    }
+    let index = absindex(state, index);
+    let top = gettop(state);
+    push_runtime(state).ok()?;
+    getfield(state, -1, cstr!("storage"));
+    pushlightuserdata(state, type_key::<T>());
+    rawget(state, -2);
+    let mut data = None;
+    if get_type(state, -1) == TTABLE {
+        pushvalue(state, index);
+        rawget(state, -2);
+        if is_data::<T>(state, -1) {
+            data = touserdata(state, -1).cast::<Data<T>>().as_ref();
+        }
+    }
+    settop(state, top);
+    data
+}
+
+/// Returns `true` if the value at `index` is a live data userdata of `T`, recognised by its metatable.
+unsafe fn is_data<T>(state: lua_State, index: i32) -> bool
+where
+    T: 'static,
+{
+    let index = absindex(state, index);
+    if get_type(state, index) != TUSERDATA || getmetatable(state, index) == 0 {
+        return false;
+    }
+    pushlightuserdata(state, type_key::<T>());
+    rawget(state, REGISTRYINDEX);
+    let made_by_t = rawequal(state, -1, -2);
+    pop!(state, 2);
+    made_by_t
+}
+
+/// Calls `object:name(...)` with the `nargs` arguments on top of the stack, so overrides defined in Lua subclasses are used.
+///
+/// Pops the arguments and returns the results converted to `R`. Errors carry a traceback.
+///
+/// # Safety
+/// `object` must be valid in `state`, and the `nargs` arguments must be on top of the stack.
+pub unsafe fn call_method<R>(
+    state: lua_State,
+    object: i32,
+    name: &str,
+    nargs: i32,
+) -> std::result::Result<R, LError>
+where
+    R: FromLua,
+{
+    let object = absindex(state, object);
+    push_runtime(state)?;
+    getfield(state, -1, cstr!("invoke"));
+    crate::remove(state, -2);
+    insert(state, -(nargs + 1));
+    pushvalue(state, object);
+    insert(state, -(nargs + 1));
+    pushlstring(state, name.as_ptr(), name.len());
+    insert(state, -(nargs + 1));
+    call(state, nargs + 2)
+}
@@ -0,0 +1,229 @@
+//! `log` and `tracing` backend that prints to the game console.
+//!
+//! Records can be emitted from any thread. They are buffered and printed by [`flush`], which must be called from the Lua thread (or pushed into a `Think` hook with [`pushflush`]).
+//! Errors go through `ErrorNoHalt`, everything else goes through `MsgC` with a colour per level, falling back to `print` outside of the game.
+//! ```ignore
+//! # use lua_shared::{self as lua, console};
+//! # unsafe fn open(state: lua::lua_State) {
+//! console::init(log::LevelFilter::Info).unwrap();
+//! log::info!("module loaded");
+//! console::flush(state);
+//! # }
+//! ```
+
+use std::{collections::VecDeque, fmt::Write, sync::Mutex};
+
+use crate::{
+    check_thread, createtable, cstr, get_type, getfield, lua_State, pcall, pop, pushcclosure,
+    pushlstring, pushnumber, setfield, Status, GLOBALSINDEX, TFUNCTION,
+};
+
+/// Maximum number of records kept between two flushes. Older records are dropped first.
+pub const BUFFER_LIMIT: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn color(self) -> (f64, f64, f64) {
+        match self {
+            Level::Error => (255.0, 90.0, 90.0),
+            Level::Warn => (255.0, 200.0, 60.0),
+            Level::Info => (220.0, 220.0, 220.0),
+            Level::Debug => (120.0, 180.0, 255.0),
+            Level::Trace => (150.0, 150.0, 150.0),
+        }
+    }
+}
+
+struct Buffer {
+    records: VecDeque<(Level, String)>,
+    dropped: usize,
+}
+
+static BUFFER: Mutex<Buffer> = Mutex::new(Buffer {
+    records: VecDeque::new(),
+    dropped: 0,
+});
+
+/// Queues a line to be printed on the next [`flush`].
+pub fn push(level: Level, message: String) {
+    let mut buffer = BUFFER.lock().unwrap_or_else(|err| err.into_inner());
+    if buffer.records.len() >= BUFFER_LIMIT {
+        buffer.records.pop_front();
+        buffer.dropped += 1;
+    }
+    buffer.records.push_back((level, message));
+}
+
+/// Prints every buffered record to the game console.
+pub unsafe fn flush(state: lua_State) {
+    check_thread(state);
+    let (records, dropped) = {
+        let mut buffer = BUFFER.lock().unwrap_or_else(|err| err.into_inner());
+        (
+            std::mem::take(&mut buffer.records),
+            std::mem::take(&mut buffer.dropped),
+        )
+    };
+    if dropped > 0 {
+        print(
+            state,
+            Level::Warn,
+            &format!("[lua-shared] dropped {} log records\n", dropped),
+        );
+    }
+    for (level, mut message) in records {
+        message.push('\n');
+        print(state, level, &message);
+    }
+}
+
+/// Pushes a Lua function that calls [`flush`] onto the stack.
+pub unsafe fn pushflush(state: lua_State) {
+    unsafe extern "C" fn flush_callback(state: lua_State) -> i32 {
+        flush(state);
+        0
+    }
+    pushcclosure(state, flush_callback, 0);
+}
+
+unsafe fn print(state: lua_State, level: Level, message: &str) {
+    if level == Level::Error {
+        getfield(state, GLOBALSINDEX, cstr!("ErrorNoHalt"));
+        if get_type(state, -1) == TFUNCTION {
+            pushlstring(state, message.as_ptr(), message.len());
+            if !matches!(pcall(state, 1, 0, 0), Status::Ok) {
+                pop!(state, 1);
+            }
+            return;
+        }
+        pop!(state, 1);
+    }
+    getfield(state, GLOBALSINDEX, cstr!("MsgC"));
+    if get_type(state, -1) == TFUNCTION {
+        let (r, g, b) = level.color();
+        createtable(state, 0, 4);
+        pushnumber(state, r);
+        setfield(state, -2, cstr!("r"));
+        pushnumber(state, g);
+        setfield(state, -2, cstr!("g"));
+        pushnumber(state, b);
+        setfield(state, -2, cstr!("b"));
+        pushnumber(state, 255.0);
+        setfield(state, -2, cstr!("a"));
+        pushlstring(state, message.as_ptr(), message.len());
+        if !matches!(pcall(state, 2, 0, 0), Status::Ok) {
+            pop!(state, 1);
+        }
+        return;
+    }
+    pop!(state, 1);
+    getfield(state, GLOBALSINDEX, cstr!("print"));
+    let message = message.strip_suffix('\n').unwrap_or(message);
+    pushlstring(state, message.as_ptr(), message.len());
+    if !matches!(pcall(state, 1, 0, 0), Status::Ok) {
+        pop!(state, 1);
+    }
+}
+
+/// [`log::Log`] implementation that queues records for [`flush`].
+pub struct ConsoleLogger {
+    level: log::LevelFilter,
+}
+
+impl ConsoleLogger {
+    pub const fn new(level: log::LevelFilter) -> Self {
+        Self { level }
+    }
+}
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            log::Level::Error => Level::Error,
+            log::Level::Warn => Level::Warn,
+            log::Level::Info => Level::Info,
+            log::Level::Debug => Level::Debug,
+            log::Level::Trace => Level::Trace,
+        };
+        push(
+            level,
+            format!(
+                "[{}] {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs a [`ConsoleLogger`] as the global logger.
+pub fn init(level: log::LevelFilter) -> std::result::Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(ConsoleLogger::new(level)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// [`tracing_subscriber::Layer`] that queues events for [`flush`].
+#[derive(Default)]
+pub struct ConsoleLayer;
+
+struct MessageVisitor(String);
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.0.push_str(value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl<SUBSCRIBER> tracing_subscriber::Layer<SUBSCRIBER> for ConsoleLayer
+where
+    SUBSCRIBER: tracing::Subscriber,
+{
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _: tracing_subscriber::layer::Context<'_, SUBSCRIBER>,
+    ) {
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            tracing::Level::ERROR => Level::Error,
+            tracing::Level::WARN => Level::Warn,
+            tracing::Level::INFO => Level::Info,
+            tracing::Level::DEBUG => Level::Debug,
+            tracing::Level::TRACE => Level::Trace,
+        };
+        let mut visitor = MessageVisitor(format!("[{}] {}: ", metadata.level(), metadata.target()));
+        event.record(&mut visitor);
+        push(level, visitor.0);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    get_type, gettop, lua_State, pushboolean, pushlstring, pushnil, pushnumber, toboolean,
+    tolstring, tonumber, typename, LError, TBOOLEAN, TNIL, TNONE, TNUMBER, TSTRING,
+};
+
+/// Conversion from values on the Lua stack.
+///
+/// `NRETS` is the number of stack slots the type is read from, starting at `index`.
+/// It is 1 for plain values, 0 for `()` and the sum of the elements for tuples, so tuples can be used to collect multiple results.
+pub trait FromLua: Sized {
+    const NRETS: i32 = 1;
+
+    /// Reads the value at `index` (an absolute index) without popping it.
+    ///
+    /// # Safety
+    /// `index` must be an absolute index of `state`, valid for `NRETS` slots.
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError>;
+}
+
+pub(crate) unsafe fn type_error(state: lua_State, index: i32, expected: &str) -> LError {
+    let got = std::ffi::CStr::from_ptr(typename(state, get_type(state, index)).cast());
+    LError::TypeError(format!(
+        "{} expected, got {}",
+        expected,
+        got.to_string_lossy()
+    ))
+}
+
+impl FromLua for () {
+    const NRETS: i32 = 0;
+
+    unsafe fn from_lua(_: lua_State, _: i32) -> std::result::Result<Self, LError> {
+        Ok(())
+    }
+}
+
+impl FromLua for bool {
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+        match get_type(state, index) {
+            TBOOLEAN | TNIL | TNONE => Ok(toboolean(state, index)),
+            _ => Err(type_error(state, index, "boolean")),
+        }
+    }
+}
+
+macro_rules! impl_float {
+    ($($ty:ty),*) => {
+        $(
+            impl FromLua for $ty {
+                unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+                    match get_type(state, index) {
+                        TNUMBER => Ok(tonumber(state, index) as $ty),
+                        _ => Err(type_error(state, index, "number")),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f64, f32);
+
+/// Integer types that Lua numbers convert to without losing anything.
+pub(crate) trait Integer: Sized {
+    /// Returns `None` if `number` is not integral or out of range.
+    fn from_number(number: f64) -> Option<Self>;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl Integer for $ty {
+                fn from_number(number: f64) -> Option<Self> {
+                    // `MAX + 1` is a power of two, so unlike `MAX` it is exact as a float.
+                    if number.fract() == 0.0 && number >= <$ty>::MIN as f64 && number < <$ty>::MAX as f64 + 1.0 {
+                        Some(number as $ty)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            impl FromLua for $ty {
+                unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+                    match get_type(state, index) {
+                        TNUMBER => {
+                            let number = tonumber(state, index);
+                            <$ty>::from_number(number).ok_or_else(|| {
+                                LError::TypeError(format!("{} expected, got {}", stringify!($ty), number))
+                            })
+                        }
+                        _ => Err(type_error(state, index, "number")),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromLua for Vec<u8> {
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+        match get_type(state, index) {
+            TSTRING => {
+                let mut len = 0;
+                let ptr = tolstring(state, index, &mut len);
+                Ok(std::slice::from_raw_parts(ptr, len).to_vec())
+            }
+            _ => Err(type_error(state, index, "string")),
+        }
+    }
+}
+
+impl FromLua for String {
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+        String::from_utf8(Vec::<u8>::from_lua(state, index)?)
+            .map_err(|err| LError::TypeError(err.to_string()))
+    }
+}
+
+impl<T> FromLua for Option<T>
+where
+    T: FromLua,
+{
+    const NRETS: i32 = T::NRETS;
+
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+        match get_type(state, index) {
+            TNIL | TNONE => Ok(None),
+            _ => T::from_lua(state, index).map(Some),
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name),+> FromLua for ($($name,)+)
+        where
+            $($name: FromLua,)+
+        {
+            const NRETS: i32 = 0 $(+ $name::NRETS)+;
+
+            #[allow(unused_assignments)]
+            unsafe fn from_lua(state: lua_State, mut index: i32) -> std::result::Result<Self, LError> {
+                Ok(($({
+                    let value = $name::from_lua(state, index)?;
+                    index += $name::NRETS;
+                    value
+                },)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+
+/// Reads `R` from the top `R::NRETS` values of the stack and pops them.
+///
+/// # Safety
+/// The stack of `state` must hold at least `R::NRETS` values.
+pub unsafe fn pop_value<R>(state: lua_State) -> std::result::Result<R, LError>
+where
+    R: FromLua,
+{
+    let result = R::from_lua(state, gettop(state) - R::NRETS + 1);
+    crate::settop(state, gettop(state) - R::NRETS);
+    result
+}
+
+/// Conversion to values on the Lua stack, the counterpart of [`FromLua`].
+pub trait IntoLua {
+    /// Pushes the value onto the stack.
+    ///
+    /// # Safety
+    /// `state` must have room for one more stack slot.
+    unsafe fn into_lua(self, state: lua_State);
+}
+
+impl IntoLua for () {
+    unsafe fn into_lua(self, state: lua_State) {
+        pushnil(state);
+    }
+}
+
+impl IntoLua for bool {
+    unsafe fn into_lua(self, state: lua_State) {
+        pushboolean(state, self as i32);
+    }
+}
+
+macro_rules! impl_into_number {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoLua for $ty {
+                unsafe fn into_lua(self, state: lua_State) {
+                    pushnumber(state, self as f64);
+                }
+            }
+        )*
+    };
+}
+
+impl_into_number!(f64, f32, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLua for &[u8] {
+    unsafe fn into_lua(self, state: lua_State) {
+        pushlstring(state, self.as_ptr(), self.len());
+    }
+}
+
+impl IntoLua for Vec<u8> {
+    unsafe fn into_lua(self, state: lua_State) {
+        self.as_slice().into_lua(state);
+    }
+}
+
+impl IntoLua for &str {
+    unsafe fn into_lua(self, state: lua_State) {
+        self.as_bytes().into_lua(state);
+    }
+}
+
+impl IntoLua for String {
+    unsafe fn into_lua(self, state: lua_State) {
+        self.as_bytes().into_lua(state);
+    }
+}
+
+impl<T> IntoLua for Option<T>
+where
+    T: IntoLua,
+{
+    unsafe fn into_lua(self, state: lua_State) {
+        match self {
+            Some(value) => value.into_lua(state),
+            None => pushnil(state),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Sending closures from worker threads to the Lua thread.
+//!
+//! A [`Dispatcher`] lives on the Lua thread and owns a bounded job queue. [`DispatchHandle`]s are `Send + Clone` and can be moved into worker threads to queue jobs or to wait for a job's result.
+//! ```no_run
+//! # use lua_shared::{self as lua, dispatch::Dispatcher};
+//! # unsafe fn open(state: lua::lua_State) {
+//! let dispatcher = Dispatcher::new(256);
+//! let handle = dispatcher.handle();
+//! std::thread::spawn(move || {
+//!     let top = handle.call(|state| unsafe { lua::gettop(state) }).unwrap();
+//!     println!("stack top on the game thread: {}", top);
+//! });
+//! // Hand the dispatcher over to Lua and call the pushed function from `Think`.
+//! dispatcher.pushpump(state);
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    ffi::c_void,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    check_thread, gettop, lua_State, pcall, pushcclosure, pushfunction, pushlightuserdata, settop,
+    timers::report_error, touserdata, traceback, upvalueindex, Status,
+};
+
+struct Job {
+    run: Option<Box<dyn FnOnce(lua_State) + Send>>,
+    /// Called once the job is done, even if it raised a Lua error and `run` never returned. Drops the reply sender of requests.
+    finish: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Job {
+    fn new<FUNC>(run: FUNC) -> Self
+    where
+        FUNC: 'static + Send + FnOnce(lua_State),
+    {
+        Self {
+            run: Some(Box::new(run)),
+            finish: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The queue is at capacity.
+    Full,
+    /// The wait for free space or for a reply timed out.
+    Timeout,
+    /// The [`Dispatcher`] was dropped, or the job panicked before replying.
+    Disconnected,
+}
+
+impl std::fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DispatchError::Full => f.write_str("dispatch queue is full"),
+            DispatchError::Timeout => f.write_str("dispatch timed out"),
+            DispatchError::Disconnected => f.write_str("dispatcher is gone"),
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    not_full: Condvar,
+    capacity: usize,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Receiving end of the job queue. Must stay on the Lua thread.
+pub struct Dispatcher {
+    shared: Arc<Shared>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher that holds at most `capacity` pending jobs.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue {
+                    jobs: VecDeque::with_capacity(capacity),
+                    closed: false,
+                }),
+                not_full: Condvar::new(),
+                capacity: capacity.max(1),
+            }),
+        }
+    }
+
+    /// Returns a new handle for queueing jobs.
+    pub fn handle(&self) -> DispatchHandle {
+        DispatchHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns the number of pending jobs.
+    pub fn pending(&self) -> usize {
+        self.shared.lock().jobs.len()
+    }
+
+    /// Runs every job that is queued at the moment of the call.
+    ///
+    /// Returns the number of jobs that were run.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread.
+    pub unsafe fn pump(&self, state: lua_State) -> usize {
+        self.pump_limit(state, usize::MAX)
+    }
+
+    /// Runs at most `limit` queued jobs.
+    ///
+    /// Jobs run in protected mode: a job that panics or raises a Lua error is dropped, and whoever waits for its reply gets [`DispatchError::Disconnected`].
+    /// Lua errors are reported with a traceback through `ErrorNoHalt`, or `print` if it does not exist.
+    /// Where LuaJIT raises errors by unwinding (x64), a Lua error can't get past the guard that catches panics and aborts the process instead, so jobs should run Lua code through [`exec`](crate::exec) or `pcall`.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread. Jobs get it as is, so it must also be a state they are written for.
+    pub unsafe fn pump_limit(&self, state: lua_State, limit: usize) -> usize {
+        check_thread(state);
+        let mut jobs = {
+            let mut queue = self.shared.lock();
+            let count = queue.jobs.len().min(limit);
+            queue.jobs.drain(..count).collect::<VecDeque<_>>()
+        };
+        self.shared.not_full.notify_all();
+        let count = jobs.len();
+        while let Some(mut job) = jobs.pop_front() {
+            run(state, &mut job);
+        }
+        count
+    }
+
+    /// Moves the dispatcher into a Lua function that calls [`Dispatcher::pump`] and pushes it onto the stack.
+    ///
+    /// Pending jobs are dropped once Lua collects the function.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread, see [`Dispatcher::pump_limit`].
+    pub unsafe fn pushpump(self, state: lua_State) {
+        pushfunction(state, move |state| {
+            self.pump(state);
+            Ok(0)
+        });
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        let jobs = {
+            let mut queue = self.shared.lock();
+            queue.closed = true;
+            std::mem::take(&mut queue.jobs)
+        };
+        self.shared.not_full.notify_all();
+        std::mem::drop(jobs);
+    }
+}
+
+/// Runs `job` in protected mode, then calls its `finish`.
+unsafe fn run(state: lua_State, job: &mut Job) {
+    unsafe extern "C" fn call_job(state: lua_State) -> i32 {
+        let job = &mut *touserdata(state, upvalueindex!(1)).cast::<Job>();
+        if let Some(run) = job.run.take() {
+            let _ = catch_unwind(AssertUnwindSafe(|| run(state)));
+        }
+        0
+    }
+    let top = gettop(state);
+    pushcclosure(state, traceback, 0);
+    pushlightuserdata(state, job as *mut Job as *const c_void);
+    pushcclosure(state, call_job, 1);
+    if !matches!(pcall(state, 0, 0, top + 1), Status::Ok) {
+        report_error(state);
+    }
+    settop(state, top);
+    if let Some(finish) = job.finish.take() {
+        finish();
+    }
+}
+
+/// Sending end of the job queue.
+#[derive(Clone)]
+pub struct DispatchHandle {
+    shared: Arc<Shared>,
+}
+
+impl DispatchHandle {
+    fn enqueue(&self, job: Job, deadline: Option<Option<Instant>>) -> Result<(), DispatchError> {
+        let mut queue = self.shared.lock();
+        loop {
+            if queue.closed {
+                return Err(DispatchError::Disconnected);
+            }
+            if queue.jobs.len() < self.shared.capacity {
+                queue.jobs.push_back(job);
+                return Ok(());
+            }
+            queue = match deadline {
+                None => return Err(DispatchError::Full),
+                Some(None) => self
+                    .shared
+                    .not_full
+                    .wait(queue)
+                    .unwrap_or_else(|err| err.into_inner()),
+                Some(Some(deadline)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(DispatchError::Timeout);
+                    }
+                    self.shared
+                        .not_full
+                        .wait_timeout(queue, deadline - now)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Queues a job, blocking while the queue is full.
+    pub fn send<FUNC>(&self, job: FUNC) -> Result<(), DispatchError>
+    where
+        FUNC: 'static + Send + FnOnce(lua_State),
+    {
+        self.enqueue(Job::new(job), Some(None))
+    }
+
+    /// Queues a job, failing with [`DispatchError::Full`] instead of blocking.
+    pub fn try_send<FUNC>(&self, job: FUNC) -> Result<(), DispatchError>
+    where
+        FUNC: 'static + Send + FnOnce(lua_State),
+    {
+        self.enqueue(Job::new(job), None)
+    }
+
+    /// Queues a job, blocking for at most `timeout` while the queue is full.
+    pub fn send_timeout<FUNC>(&self, job: FUNC, timeout: Duration) -> Result<(), DispatchError>
+    where
+        FUNC: 'static + Send + FnOnce(lua_State),
+    {
+        self.enqueue(Job::new(job), Some(Some(Instant::now() + timeout)))
+    }
+
+    /// Queues a job whose result can be waited for through the returned [`Reply`].
+    pub fn request<FUNC, RET>(&self, job: FUNC) -> Result<Reply<RET>, DispatchError>
+    where
+        FUNC: 'static + Send + FnOnce(lua_State) -> RET,
+        RET: 'static + Send,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        // A Lua error skips the end of the job, so the sender is also reachable from `finish`.
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let reply = sender.clone();
+        let mut job = Job::new(move |state| {
+            let value = job(state);
+            let sender = sender.lock().unwrap_or_else(|err| err.into_inner()).take();
+            if let Some(sender) = sender {
+                let _ = sender.send(value);
+            }
+        });
+        job.finish = Some(Box::new(move || {
+            reply.lock().unwrap_or_else(|err| err.into_inner()).take();
+        }));
+        self.enqueue(job, Some(None))?;
+        Ok(Reply { receiver })
+    }
+
+    /// Queues a job and blocks until the Lua thread ran it.
+    ///
+    /// Never call this from the Lua thread itself, it would wait forever.
+    pub fn call<FUNC, RET>(&self, job: FUNC) -> Result<RET, DispatchError>
+    where
+        FUNC: 'static + Send + FnOnce(lua_State) -> RET,
+        RET: 'static + Send,
+    {
+        self.request(job)?.wait()
+    }
+}
+
+/// Pending result of a job queued with [`DispatchHandle::request`].
+pub struct Reply<RET> {
+    receiver: mpsc::Receiver<RET>,
+}
+
+impl<RET> Reply<RET> {
+    /// Blocks until the job was run.
+    pub fn wait(self) -> Result<RET, DispatchError> {
+        self.receiver
+            .recv()
+            .map_err(|_| DispatchError::Disconnected)
+    }
+
+    /// Blocks for at most `timeout` until the job was run.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<RET, DispatchError> {
+        self.receiver
+            .recv_timeout(timeout)
+            .map_err(|err| match err {
+                mpsc::RecvTimeoutError::Timeout => DispatchError::Timeout,
+                mpsc::RecvTimeoutError::Disconnected => DispatchError::Disconnected,
+            })
+    }
+
+    /// Returns the result if the job was already run.
+    pub fn try_get(&self) -> Option<Result<RET, DispatchError>> {
+        match self.receiver.try_recv() {
+            Ok(value) => Some(Ok(value)),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(DispatchError::Disconnected)),
+        }
+    }
+}
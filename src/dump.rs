@@ -1,6 +1,9 @@
 use std::ffi::c_void;
 
-use crate::{lua_State, lua_dump, LError};
+use crate::{
+    cstr, getfield, lua_State, lua_dump, pcall, pop, pop_message, pushboolean, pushvalue, remove,
+    tolstring, LError, Status, GLOBALSINDEX,
+};
 
 // TODO: Make better writer error handling.
 pub unsafe fn dump<WRITER>(
@@ -34,3 +37,35 @@ where
         any @ _ => Err(LError::DumpError(any)),
     }
 }
+
+/// Dumps the function on the top of the stack like [`dump`], optionally stripping debug info (line numbers, local and upvalue names).
+///
+/// `lua_dump` always keeps debug info, so stripped dumps go through `string.dump`, which has to be loaded. The function is left on the stack.
+///
+/// # Safety
+/// The top of the stack of `state` must be a Lua function, with room for three more slots above it.
+pub unsafe fn dump_strip<WRITER>(
+    state: lua_State,
+    buffer_writer: &mut WRITER,
+    strip: bool,
+) -> std::result::Result<(), LError>
+where
+    WRITER: std::io::Write,
+{
+    if !strip {
+        return dump(state, buffer_writer);
+    }
+    getfield(state, GLOBALSINDEX, cstr!("string"));
+    getfield(state, -1, cstr!("dump"));
+    remove(state, -2);
+    pushvalue(state, -2);
+    pushboolean(state, 1);
+    if pcall(state, 2, 1, 0) != Status::Ok {
+        return Err(LError::RuntimeMessage(pop_message(state)));
+    }
+    let mut len = 0;
+    let ptr = tolstring(state, -1, &mut len);
+    let written = buffer_writer.write_all(std::slice::from_raw_parts(ptr, len));
+    pop!(state, 1);
+    written.map_err(LError::IoError)
+}
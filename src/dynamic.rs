@@ -0,0 +1,227 @@
+//! Runtime loading of `lua_shared` (`dynamic` feature).
+//!
+//! Nothing is linked at build time. The C API is resolved into a function table the first time it is used, or explicitly with [`load`] or [`load_from`], which report missing libraries and symbols as errors instead of the binary failing to load.
+//! [`load`] first looks for the library in the running process (the game has already loaded it), then tries the usual file names.
+//! ```no_run
+//! # use lua_shared as lua;
+//! # fn open() -> Result<(), lua::dynamic::DynamicError> {
+//! if lua::dynamic::load_from("garrysmod/bin/lua_shared_srv.so").is_err() {
+//!     lua::dynamic::load()?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ffi::OsStr,
+    sync::{Mutex, OnceLock},
+};
+
+use libloading::Library;
+
+use crate::{lua_State, Functions};
+
+/// File names tried by [`load`], in order.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub const LIBRARY_NAMES: &[&str] = &[
+    "lua_shared.so",
+    "bin/linux64/lua_shared.so",
+    "linux64/lua_shared.so",
+];
+/// File names tried by [`load`], in order.
+#[cfg(all(target_os = "linux", target_pointer_width = "32", feature = "client"))]
+pub const LIBRARY_NAMES: &[&str] = &[
+    "lua_shared.so",
+    "lua_shared_srv.so",
+    "bin/lua_shared.so",
+    "bin/lua_shared_srv.so",
+    "bin/linux32/lua_shared.so",
+    "garrysmod/bin/lua_shared.so",
+    "garrysmod/bin/lua_shared_srv.so",
+];
+/// File names tried by [`load`], in order.
+#[cfg(all(
+    target_os = "linux",
+    target_pointer_width = "32",
+    not(feature = "client")
+))]
+pub const LIBRARY_NAMES: &[&str] = &[
+    "lua_shared_srv.so",
+    "lua_shared.so",
+    "bin/lua_shared_srv.so",
+    "bin/lua_shared.so",
+    "bin/linux32/lua_shared.so",
+    "garrysmod/bin/lua_shared_srv.so",
+    "garrysmod/bin/lua_shared.so",
+];
+/// File names tried by [`load`], in order.
+#[cfg(target_os = "windows")]
+pub const LIBRARY_NAMES: &[&str] = &[
+    "lua_shared.dll",
+    "bin/lua_shared.dll",
+    "bin/win64/lua_shared.dll",
+    "garrysmod/bin/lua_shared.dll",
+];
+/// File names tried by [`load`], in order.
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub const LIBRARY_NAMES: &[&str] = &[];
+
+type ErrorFunction = unsafe extern "C-unwind" fn(lua_State, *const u8, ...) -> !;
+
+struct Api {
+    functions: Functions,
+    error: ErrorFunction,
+    // Keeps the symbols valid.
+    _library: Library,
+}
+
+static API: OnceLock<Api> = OnceLock::new();
+static LOADING: Mutex<()> = Mutex::new(());
+
+#[derive(Debug)]
+pub enum DynamicError {
+    /// None of the libraries could be opened. Holds the error for every name tried.
+    NotFound(Vec<(String, libloading::Error)>),
+    /// The library was opened but lacks some of the functions.
+    MissingSymbols(String, Vec<&'static str>),
+    /// A library has already been loaded.
+    AlreadyLoaded,
+}
+
+impl std::fmt::Display for DynamicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DynamicError::NotFound(errors) => {
+                write!(f, "lua_shared not found")?;
+                // libloading's errors already name the file.
+                for (_, err) in errors {
+                    write!(f, "\n\t{}", err)?;
+                }
+                Ok(())
+            }
+            DynamicError::MissingSymbols(name, symbols) => {
+                write!(f, "{} is missing {}", name, symbols.join(", "))
+            }
+            DynamicError::AlreadyLoaded => write!(f, "lua_shared is already loaded"),
+        }
+    }
+}
+
+impl std::error::Error for DynamicError {}
+
+fn resolve(name: String, library: Library) -> Result<Api, DynamicError> {
+    unsafe {
+        let functions = Functions::resolve(&library)
+            .map_err(|missing| DynamicError::MissingSymbols(name.clone(), missing))?;
+        let error = library
+            .get::<ErrorFunction>(b"luaL_error\0")
+            .map(|symbol| *symbol)
+            .map_err(|_| DynamicError::MissingSymbols(name, vec!["luaL_error"]))?;
+        Ok(Api {
+            functions,
+            error,
+            _library: library,
+        })
+    }
+}
+
+/// Returns the library if the process already has it loaded.
+unsafe fn already_loaded() -> Option<(String, Library)> {
+    #[cfg(unix)]
+    {
+        use libloading::os::unix;
+
+        let this = unix::Library::this();
+        if this.get::<*mut std::ffi::c_void>(b"lua_gettop\0").is_ok() {
+            return Some((String::from("<process>"), this.into()));
+        }
+        // `RTLD_NOLOAD` only returns libraries that are already loaded.
+        const RTLD_NOLOAD: i32 = 4;
+        for name in LIBRARY_NAMES {
+            if let Ok(library) = unix::Library::open(Some(name), unix::RTLD_NOW | RTLD_NOLOAD) {
+                return Some((name.to_string(), library.into()));
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        for name in LIBRARY_NAMES {
+            if let Ok(library) = libloading::os::windows::Library::open_already_loaded(name) {
+                return Some((name.to_string(), library.into()));
+            }
+        }
+    }
+    None
+}
+
+/// Loads `lua_shared`, preferring the copy the process has already loaded.
+///
+/// Does nothing if a library is already loaded.
+pub fn load() -> Result<(), DynamicError> {
+    let _guard = LOADING.lock().unwrap_or_else(|err| err.into_inner());
+    if API.get().is_some() {
+        return Ok(());
+    }
+    let api = match unsafe { already_loaded() } {
+        Some((name, library)) => resolve(name, library)?,
+        None => {
+            let mut errors = Vec::new();
+            let mut found = None;
+            for name in LIBRARY_NAMES {
+                match unsafe { Library::new(name) } {
+                    Ok(library) => {
+                        found = Some((name.to_string(), library));
+                        break;
+                    }
+                    Err(err) => errors.push((name.to_string(), err)),
+                }
+            }
+            let (name, library) = found.ok_or(DynamicError::NotFound(errors))?;
+            resolve(name, library)?
+        }
+    };
+    let _ = API.set(api);
+    Ok(())
+}
+
+/// Loads the library at `path`, e.g. to choose between `lua_shared` and `lua_shared_srv` at runtime.
+///
+/// Fails with [`DynamicError::AlreadyLoaded`] if a library is already loaded.
+pub fn load_from<PATH>(path: PATH) -> Result<(), DynamicError>
+where
+    PATH: AsRef<OsStr>,
+{
+    let _guard = LOADING.lock().unwrap_or_else(|err| err.into_inner());
+    if API.get().is_some() {
+        return Err(DynamicError::AlreadyLoaded);
+    }
+    let name = path.as_ref().to_string_lossy().into_owned();
+    let library = unsafe { Library::new(path.as_ref()) }
+        .map_err(|err| DynamicError::NotFound(vec![(name.clone(), err)]))?;
+    let _ = API.set(resolve(name, library)?);
+    Ok(())
+}
+
+pub fn is_loaded() -> bool {
+    API.get().is_some()
+}
+
+fn api() -> &'static Api {
+    match API.get() {
+        Some(api) => api,
+        None => {
+            if let Err(err) = load() {
+                panic!("{}", err);
+            }
+            API.get().expect("lua_shared is loaded")
+        }
+    }
+}
+
+pub(crate) fn functions() -> &'static Functions {
+    &api().functions
+}
+
+pub(crate) fn error_function() -> ErrorFunction {
+    api().error
+}
@@ -0,0 +1,157 @@
+//! Lua modules compiled into the binary.
+//!
+//! [`embed_lua!`](crate::embed_lua) includes every `.lua` and `.luac` file under a directory with `include_bytes!`, and [`Embedded`] makes them available to `require`, either through a searcher in `package.loaders` or through `package.preload`.
+//! Files can be source or bytecode; `mymod/util.lua` is found as `mymod.util` and `mymod/init.lua` as `mymod`.
+//! ```ignore
+//! # use lua_shared::{self as lua, embed_lua};
+//! # unsafe fn open(state: lua::lua_State) {
+//! static MODULES: lua::embed::Embedded = embed_lua!("lua/");
+//! MODULES.install(state);
+//! # }
+//! ```
+
+use crate::{
+    call, cstr, get_type, getfield, gettop, insert, loadx, lua_State, objlen, pop, pushfunction,
+    pushlstring, rawgeti, rawseti, settable, LError, Lchecklstring, GLOBALSINDEX, TTABLE,
+};
+
+/// A file included by [`embed_lua!`](crate::embed_lua).
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedFile {
+    /// Path relative to the embedded directory, with `/` as the separator.
+    pub path: &'static str,
+    /// Source or bytecode.
+    pub chunk: &'static [u8],
+}
+
+impl EmbeddedFile {
+    /// Returns the module name the file is required as.
+    pub fn module_name(&self) -> String {
+        let path = self.path.trim_start_matches("./");
+        let path = match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => &path[..dot],
+            _ => path,
+        };
+        let path = match path.strip_suffix("/init") {
+            Some(path) => path,
+            None => path,
+        };
+        path.replace('/', ".")
+    }
+
+    /// Loads the file and pushes the resulting function onto the stack.
+    ///
+    /// The chunk is named `@path`. Bytecode is accepted, since it's part of the binary.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state with room for one more stack slot.
+    pub unsafe fn load(&self, state: lua_State) -> std::result::Result<(), LError> {
+        let chunk_name = format!("@{}\0", self.path);
+        loadx(
+            state,
+            &mut &self.chunk[..],
+            chunk_name.as_ptr(),
+            cstr!("bt"),
+        )
+    }
+}
+
+/// A set of embedded files, usually created by [`embed_lua!`](crate::embed_lua).
+#[derive(Clone, Copy, Debug)]
+pub struct Embedded {
+    files: &'static [EmbeddedFile],
+}
+
+impl Embedded {
+    pub const fn new(files: &'static [EmbeddedFile]) -> Self {
+        Self { files }
+    }
+
+    pub fn files(&self) -> &'static [EmbeddedFile] {
+        self.files
+    }
+
+    /// Returns the file `require(name)` resolves to.
+    pub fn find(&self, name: &str) -> Option<&'static EmbeddedFile> {
+        self.files.iter().find(|file| file.module_name() == name)
+    }
+
+    /// Adds a searcher for the embedded files to `package.loaders`, right after the `package.preload` one.
+    ///
+    /// Embedded modules take precedence over files on disk.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread.
+    pub unsafe fn install(self, state: lua_State) {
+        getfield(state, GLOBALSINDEX, cstr!("package"));
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 1);
+            return;
+        }
+        getfield(state, -1, cstr!("loaders"));
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 2);
+            return;
+        }
+        let len = objlen(state, -1) as i32;
+        for slot in (2..=len).rev() {
+            rawgeti(state, -1, slot);
+            rawseti(state, -2, slot + 1);
+        }
+        pushfunction(state, move |state| {
+            let mut len = 0;
+            let name = Lchecklstring(state, 1, &mut len);
+            let name = String::from_utf8_lossy(std::slice::from_raw_parts(name, len));
+            match self.find(&name) {
+                Some(file) => {
+                    file.load(state).map_err(|err| {
+                        format!(
+                            "error loading module '{}' from embedded file '{}':\n\t{}",
+                            name, file.path, err
+                        )
+                    })?;
+                    Ok(1)
+                }
+                None => {
+                    let message = format!("\n\tno embedded module '{}'", name);
+                    pushlstring(state, message.as_ptr(), message.len());
+                    Ok(1)
+                }
+            }
+        });
+        rawseti(state, -2, 2.min(len + 1));
+        pop!(state, 2);
+    }
+
+    /// Sets `package.preload[name]` for every embedded file.
+    ///
+    /// Use this instead of [`Embedded::install`] when `require` doesn't go through `package.loaders`. Files are only loaded when required.
+    ///
+    /// # Safety
+    /// Same as [`Embedded::install`].
+    pub unsafe fn preload(self, state: lua_State) {
+        getfield(state, GLOBALSINDEX, cstr!("package"));
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 1);
+            return;
+        }
+        getfield(state, -1, cstr!("preload"));
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 2);
+            return;
+        }
+        for file in self.files {
+            let name = file.module_name();
+            pushfunction(state, move |state| {
+                file.load(state).map_err(|err| err.to_string())?;
+                insert(state, 1);
+                call(state, gettop(state) - 1, 1);
+                Ok(1)
+            });
+            pushlstring(state, name.as_ptr(), name.len());
+            insert(state, -2);
+            settable(state, -3);
+        }
+        pop!(state, 2);
+    }
+}
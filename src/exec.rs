@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crate::{
+    convert::pop_value, gettop, insert, lua_State, pcall, pop_message, remove, traceback, FromLua,
+    LError, Lloadbufferx, LoadMode, Status,
+};
+
+/// Turns `name` into a chunk name: names that already start with `=` or `@` are kept, anything else is prefixed with `=`.
+fn chunk_name(name: &str) -> Vec<u8> {
+    let mut chunk_name = Vec::with_capacity(name.len() + 2);
+    if !name.starts_with(['=', '@']) {
+        chunk_name.push(b'=');
+    }
+    chunk_name.extend_from_slice(name.as_bytes());
+    chunk_name.push(0);
+    chunk_name
+}
+
+/// [`Lloadbufferx`] with errors popped into [`LError`].
+pub(crate) unsafe fn load_buffer(
+    state: lua_State,
+    buffer: &[u8],
+    chunk_name: *const u8,
+    mode: LoadMode,
+) -> std::result::Result<(), LError> {
+    match Lloadbufferx(
+        state,
+        buffer.as_ptr(),
+        buffer.len(),
+        chunk_name,
+        mode.as_ptr(),
+    ) {
+        Status::Ok => Ok(()),
+        Status::MemoryError => Err(LError::MemoryError(pop_message(state))),
+        _ => Err(LError::SyntaxError(pop_message(state))),
+    }
+}
+
+/// Calls the function below the `nargs` arguments on top of the stack with a traceback handler and pops its results into `R`.
+pub(crate) unsafe fn call<R>(state: lua_State, nargs: i32) -> std::result::Result<R, LError>
+where
+    R: FromLua,
+{
+    let base = gettop(state) - nargs;
+    crate::pushcclosure(state, traceback, 0);
+    insert(state, base);
+    let status = pcall(state, nargs, R::NRETS, base);
+    remove(state, base);
+    match status {
+        Status::Ok => pop_value(state),
+        Status::MemoryError => Err(LError::MemoryError(pop_message(state))),
+        _ => Err(LError::RuntimeMessage(pop_message(state))),
+    }
+}
+
+/// Runs `code` and returns its results converted to `R`.
+///
+/// `name` is used as the chunk name: `"=name"` and `"@path"` are taken as is, anything else is prefixed with `=`.
+/// Runtime errors carry a traceback. The stack is left as it was.
+/// Only source code is accepted, binary chunks have to go through [`load_buffer_checked`](crate::load_buffer_checked).
+/// ```no_run
+/// # use lua_shared as lua;
+/// # unsafe fn run(state: lua::lua_State) -> Result<(), lua::LError> {
+/// let (sum, name): (f64, String) = lua::exec(state, "return 1 + 2, 'three'", "example")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for the results.
+pub unsafe fn exec<R>(state: lua_State, code: &str, name: &str) -> std::result::Result<R, LError>
+where
+    R: FromLua,
+{
+    load_buffer(
+        state,
+        code.as_bytes(),
+        chunk_name(name).as_ptr(),
+        LoadMode::Text,
+    )?;
+    call(state, 0)
+}
+
+/// Runs the file at `path` and returns its results converted to `R`.
+///
+/// Works like `luaL_loadfile`: the chunk is named `@path`, a UTF-8 BOM is skipped and so is a first line starting with `#`.
+/// Unlike `luaL_loadfile` binary chunks are refused, like in [`exec`].
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for the results.
+pub unsafe fn exec_file<R, PATH>(state: lua_State, path: PATH) -> std::result::Result<R, LError>
+where
+    R: FromLua,
+    PATH: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut buffer = std::fs::read(path).map_err(LError::IoError)?;
+    if buffer.starts_with(b"\xEF\xBB\xBF") {
+        buffer.drain(..3);
+    }
+    if buffer.starts_with(b"#") {
+        // Keep the newline so line numbers stay right.
+        let end = buffer
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(buffer.len());
+        buffer.drain(..end);
+    }
+    let name = format!("@{}", path.display());
+    load_buffer(state, &buffer, chunk_name(&name).as_ptr(), LoadMode::Text)?;
+    call(state, 0)
+}
@@ -0,0 +1,73 @@
+use crate::{
+    cstr, gethook, hook, lua_Debug, lua_State, sethook, Lerror, MASKCALL, MASKCOUNT, MASKRET,
+};
+
+const INTERRUPT_MASK: i32 = MASKCALL | MASKRET | MASKCOUNT;
+
+unsafe extern "C-unwind" fn interrupt_hook(state: lua_State, _: *mut lua_Debug) {
+    sethook(state, None, 0, 0);
+    Lerror(state, cstr!("interrupted"));
+}
+
+/// Handle that aborts whatever Lua code is running in a state, from any thread.
+///
+/// [`InterruptHandle::interrupt`] only installs a one-shot hook with [`sethook`] (which is safe to do asynchronously), and the hook raises an `"interrupted"` error at the next instruction, call or return.
+/// The hook replaces any other hook that is set at the moment, including the one of [`pcall_with_budget`](crate::pcall_with_budget).
+/// ```no_run
+/// # use lua_shared::{self as lua, InterruptHandle};
+/// # use std::time::Duration;
+/// # unsafe fn run(state: lua::lua_State) {
+/// let handle = InterruptHandle::new(state);
+/// let watchdog = handle.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(Duration::from_millis(50));
+///     watchdog.interrupt();
+/// });
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    state: usize,
+}
+
+impl InterruptHandle {
+    /// Creates a handle for `state`. The state must outlive every copy of the handle.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state, and stay valid until the last copy of the handle is dropped.
+    pub unsafe fn new(state: lua_State) -> Self {
+        Self {
+            state: state as usize,
+        }
+    }
+
+    /// Aborts the running Lua code at the next instruction.
+    ///
+    /// If no Lua code is running, the error is raised as soon as some starts running, unless [`InterruptHandle::cancel`] is called first.
+    pub fn interrupt(&self) {
+        unsafe {
+            sethook(
+                self.state as lua_State,
+                Some(hook(interrupt_hook)),
+                INTERRUPT_MASK,
+                1,
+            )
+        };
+    }
+
+    /// Returns `true` if an interrupt is pending.
+    pub fn is_pending(&self) -> bool {
+        unsafe { gethook(self.state as lua_State) }
+            .is_some_and(|current| current as usize == hook(interrupt_hook) as usize)
+    }
+
+    /// Removes a pending interrupt. Must be called from the Lua thread.
+    ///
+    /// # Safety
+    /// Must not race with Lua code running in the state, which is why it belongs on the Lua thread.
+    pub unsafe fn cancel(&self) {
+        if self.is_pending() {
+            sethook(self.state as lua_State, None, 0, 0);
+        }
+    }
+}
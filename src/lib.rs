@@ -1,12 +1,78 @@
 #![allow(non_camel_case_types)]
 
-use std::{ffi::c_void, ptr::null_mut};
+use std::ffi::c_void;
 
 mod loadx;
 pub use loadx::loadx;
 
 mod dump;
-pub use dump::dump;
+pub use dump::{dump, dump_strip};
+
+mod alloc;
+pub use alloc::{Lua, MemoryStats};
+
+mod budget;
+pub use budget::{pcall_with_budget, Budget};
+
+mod interrupt;
+pub use interrupt::InterruptHandle;
+
+mod convert;
+pub use convert::{FromLua, IntoLua};
+
+mod exec;
+pub use exec::{exec, exec_file};
+
+mod policy;
+#[cfg(feature = "sha256")]
+pub use policy::sha256;
+pub use policy::{load_buffer_checked, loadx_checked, LoadPolicy};
+
+#[cfg(feature = "sha256")]
+mod cache;
+#[cfg(feature = "sha256")]
+pub use cache::BytecodeCache;
+
+mod stack;
+#[doc(hidden)]
+pub use stack::log_stack;
+pub use stack::{debug_stack, StackDump, StackSlot};
+
+mod value;
+pub use value::{Reference, Table, Value, DEFAULT_DEPTH_LIMIT};
+
+mod transfer;
+pub use transfer::{register_clone_hook, transfer, CloneHook};
+
+mod state;
+pub use state::{check_thread, is_owner_thread, State};
+
+pub mod timers;
+
+pub mod dispatch;
+
+pub mod sandbox;
+
+pub mod class;
+
+pub mod userdata;
+
+pub mod bytecode;
+
+pub mod embed;
+pub use lua_shared_macros::embed_lua;
+
+#[cfg(feature = "build")]
+pub mod build;
+
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "log")]
+pub mod console;
 
 #[macro_export]
 macro_rules! pop {
@@ -47,6 +113,17 @@ pub static REGISTRYINDEX: i32 = -10000;
 pub static ENVIRONINDEX: i32 = -10001;
 pub static GLOBALSINDEX: i32 = -10002;
 
+pub const TNONE: i32 = -1;
+pub const TNIL: i32 = 0;
+pub const TBOOLEAN: i32 = 1;
+pub const TLIGHTUSERDATA: i32 = 2;
+pub const TNUMBER: i32 = 3;
+pub const TSTRING: i32 = 4;
+pub const TTABLE: i32 = 5;
+pub const TFUNCTION: i32 = 6;
+pub const TUSERDATA: i32 = 7;
+pub const TTHREAD: i32 = 8;
+
 pub type lua_State = *mut c_void;
 pub type lua_CFunction = unsafe extern "C" fn(state: lua_State) -> i32;
 pub type lua_Reader =
@@ -57,10 +134,58 @@ pub type lua_Writer = unsafe extern "C" fn(
     size: usize,
     userdata: *mut c_void,
 ) -> i32;
+pub type lua_Alloc = unsafe extern "C" fn(
+    userdata: *mut c_void,
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+) -> *mut c_void;
+pub type lua_Hook = unsafe extern "C" fn(state: lua_State, ar: *mut lua_Debug);
+
+/// Turns a `C-unwind` function into a [`lua_CFunction`].
+///
+/// On x64 LuaJIT raises errors by unwinding the stack, which aborts the process when it reaches a plain `extern "C"` Rust function.
+/// Functions that can raise Lua errors, themselves or through the `luaL_check*` functions or Rust callbacks, are written as `extern "C-unwind"` and pushed through this.
+pub(crate) fn cfunction(
+    function: unsafe extern "C-unwind" fn(state: lua_State) -> i32,
+) -> lua_CFunction {
+    // Same calling convention; only Lua calls the function through the pointer.
+    unsafe { std::mem::transmute(function) }
+}
+
+/// Turns a `C-unwind` function into a [`lua_Hook`], for hooks that raise Lua errors. See [`cfunction`].
+pub(crate) fn hook(
+    function: unsafe extern "C-unwind" fn(state: lua_State, ar: *mut lua_Debug),
+) -> lua_Hook {
+    unsafe { std::mem::transmute(function) }
+}
+
 pub type Result = std::result::Result<i32, Box<dyn std::error::Error>>;
 
+pub const MASKCALL: i32 = 1 << 0;
+pub const MASKRET: i32 = 1 << 1;
+pub const MASKLINE: i32 = 1 << 2;
+pub const MASKCOUNT: i32 = 1 << 3;
+
+/// A structure used to carry different pieces of information about an active function.
 #[repr(C)]
 #[derive(Debug)]
+pub struct lua_Debug {
+    pub event: i32,
+    pub name: *const u8,
+    pub namewhat: *const u8,
+    pub what: *const u8,
+    pub source: *const u8,
+    pub currentline: i32,
+    pub nups: i32,
+    pub linedefined: i32,
+    pub lastlinedefined: i32,
+    pub short_src: [u8; 60],
+    pub i_ci: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     Ok = 0,
     Yield = 1,
@@ -73,32 +198,183 @@ pub enum Status {
 #[derive(Debug)]
 pub enum LError {
     RuntimeError,
+    /// A runtime error, with its message.
+    RuntimeMessage(String),
     SyntaxError(String),
     MemoryError(String),
     DumpError(i32),
+    Timeout,
+    BudgetExceeded,
+    TypeError(String),
+    IoError(std::io::Error),
+    BytecodeError(String),
+    Rejected(String),
+}
+
+impl std::fmt::Display for LError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LError::RuntimeError => f.write_str("runtime error"),
+            LError::RuntimeMessage(message)
+            | LError::SyntaxError(message)
+            | LError::MemoryError(message)
+            | LError::TypeError(message)
+            | LError::BytecodeError(message)
+            | LError::Rejected(message) => f.write_str(message),
+            LError::DumpError(code) => write!(f, "lua_dump failed with code {}", code),
+            LError::Timeout => f.write_str("timed out"),
+            LError::BudgetExceeded => f.write_str("instruction budget exceeded"),
+            LError::IoError(err) => err.fmt(f),
+        }
+    }
 }
 
+impl std::error::Error for LError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LoadMode {
     Any,
     Binary,
     Text,
 }
 
+impl LoadMode {
+    /// Returns the mode string expected by [`loadx`] and [`Lloadbufferx`].
+    pub fn as_ptr(self) -> *const u8 {
+        match self {
+            LoadMode::Any => cstr!("bt"),
+            LoadMode::Binary => cstr!("b"),
+            LoadMode::Text => cstr!("t"),
+        }
+    }
+}
+
+/// Declares the C API: as an `extern "C"` block, or with the `dynamic` feature as wrappers around a [`Functions`] table resolved at runtime.
+macro_rules! lua_functions {
+    ($(
+        $(#[doc = $doc:literal])*
+        #[link_name = $symbol:literal]
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;
+    )*) => {
+        #[cfg(not(feature = "dynamic"))]
+        extern "C-unwind" {
+            $(
+                $(#[doc = $doc])*
+                #[link_name = $symbol]
+                $vis fn $name($($arg: $ty),*) $(-> $ret)?;
+            )*
+        }
+
+        $(
+            #[cfg(feature = "dynamic")]
+            $(#[doc = $doc])*
+            #[inline]
+            #[allow(non_snake_case, clippy::missing_safety_doc)]
+            $vis unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                (dynamic::functions().$name)($($arg),*)
+            }
+        )*
+
+        /// Every function of the C API, resolved from the loaded library.
+        #[cfg(feature = "dynamic")]
+        #[allow(non_snake_case)]
+        pub(crate) struct Functions {
+            $($name: unsafe extern "C-unwind" fn($($ty),*) $(-> $ret)?,)*
+        }
+
+        #[cfg(feature = "dynamic")]
+        impl Functions {
+            /// Resolves every function, or returns the names of the missing symbols.
+            #[allow(non_snake_case)]
+            pub(crate) unsafe fn resolve(
+                library: &libloading::Library,
+            ) -> std::result::Result<Self, Vec<&'static str>> {
+                let mut missing = Vec::new();
+                $(
+                    let $name = library
+                        .get::<unsafe extern "C-unwind" fn($($ty),*) $(-> $ret)?>(concat!($symbol, "\0").as_bytes())
+                        .map(|symbol| *symbol)
+                        .ok();
+                    if $name.is_none() {
+                        missing.push($symbol);
+                    }
+                )*
+                if !missing.is_empty() {
+                    return Err(missing);
+                }
+                Ok(Self {
+                    $($name: $name.unwrap(),)*
+                })
+            }
+        }
+    };
+}
+
+#[cfg(all(feature = "client", feature = "server"))]
+compile_error!(
+    "the `client` and `server` features select different libraries and can't be enabled together"
+);
+
 // LOL
 // This piece of "code" greatly reduces shit that needed to be in `build.rs`
-#[cfg(all(target_os = "linux", target_pointer_width = "32"))]
+// With `luajit-system` or `luajit-vendored` the build script links LuaJIT instead, with `dynamic` nothing is linked.
+#[cfg(all(
+    target_os = "linux",
+    target_pointer_width = "32",
+    not(feature = "client"),
+    not(any(
+        feature = "luajit-system",
+        feature = "luajit-vendored",
+        feature = "dynamic"
+    ))
+))]
 #[link(name = ":lua_shared_srv.so", kind = "dylib")]
 extern "C" {}
 
-#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+#[cfg(all(
+    target_os = "linux",
+    target_pointer_width = "32",
+    feature = "client",
+    not(any(
+        feature = "luajit-system",
+        feature = "luajit-vendored",
+        feature = "dynamic"
+    ))
+))]
+#[link(name = ":lua_shared.so", kind = "dylib")]
+extern "C" {}
+
+#[cfg(all(
+    target_os = "linux",
+    target_pointer_width = "64",
+    not(any(
+        feature = "luajit-system",
+        feature = "luajit-vendored",
+        feature = "dynamic"
+    ))
+))]
 #[link(name = ":lua_shared.so", kind = "dylib")]
 extern "C" {}
 
-#[cfg(target_os = "windows")]
+#[cfg(all(
+    target_os = "windows",
+    not(any(
+        feature = "luajit-system",
+        feature = "luajit-vendored",
+        feature = "dynamic"
+    ))
+))]
 #[link(name = "lua_shared", kind = "dylib")]
 extern "C" {}
 
-extern "C" {
+lua_functions! {
     // state manipulation
 
     /// Creates a new Lua state.
@@ -107,6 +383,13 @@ extern "C" {
     /// Returns the new state, or `NULL` if there is a memory allocation error.
     #[link_name = "luaL_newstate"]
     pub fn newstate() -> lua_State;
+    /// Creates a new, independent state. Returns `NULL` if cannot create the state (due to lack of memory).
+    /// The argument `alloc` is the allocator function; Lua does all memory allocation for this state through this function.
+    /// The second argument, `userdata`, is an opaque pointer that Lua simply passes to the allocator in every call.
+    ///
+    /// 64-bit LuaJIT builds without GC64 refuse custom allocators and always return `NULL`.
+    #[link_name = "lua_newstate"]
+    pub fn newstate_alloc(alloc: lua_Alloc, userdata: *mut c_void) -> lua_State;
     /// Destroys all objects in the given Lua state (calling the corresponding garbage-collection metamethods, if any) and frees all dynamic memory used by this state.
     /// In several platforms, you may not need to call this function, because all resources are naturally released when the host program ends.
     /// On the other hand, long-running programs that create multiple states, such as daemons or web servers, will probably need to close states as soon as they are not needed.
@@ -362,6 +645,7 @@ extern "C" {
     /// In case of runtime errors, this function will be called with the error message and its return value will be the message returned on the stack by [`pcall`] (`lua_pcall`).
     #[link_name = "lua_pcall"]
     pub fn pcall(state: lua_State, nargs: i32, nrets: i32, errfunc: i32) -> Status;
+    #[link_name = "lua_loadx"]
     fn lua_loadx(
         state: lua_State,
         reader: lua_Reader,
@@ -369,6 +653,7 @@ extern "C" {
         chunk_name: *const u8,
         mode: *const u8,
     ) -> Status;
+    #[link_name = "lua_dump"]
     fn lua_dump(state: lua_State, writer: lua_Writer, userdata: *mut c_void) -> i32;
 
     // miscellaneous functions
@@ -376,8 +661,48 @@ extern "C" {
     /// Generates a Lua error. The error message (which can actually be a Lua value of any type) must be on the stack top. This function does a long jump, and therefore never returns. (see [`luaL_error`](https://www.lua.org/manual/5.1/manual.html#luaL_error)).
     #[link_name = "lua_error"]
     pub fn error(state: lua_State) -> !;
-    // #[link_name = "lua_next"]
-    // fn lua_next(state: lua_State, index: i32) -> i32;
+    /// Sets a new panic function and returns the old one.
+    ///
+    /// If an error happens outside any protected environment, Lua calls a _panic function_ and then calls `exit(EXIT_FAILURE)`, thus exiting the host application.
+    /// Your panic function can avoid this exit by never returning (e.g., doing a long jump).
+    #[link_name = "lua_atpanic"]
+    pub fn atpanic(state: lua_State, panicf: lua_CFunction) -> lua_CFunction;
+    /// Gets information about a closure's upvalue. Pushes the upvalue's value onto the stack and returns its name.
+    /// `function` points to the closure in the stack, `n` is the upvalue number (upvalues have no particular order).
+    ///
+    /// Returns `NULL` (and pushes nothing) when `n` is greater than the number of upvalues. For C functions, the empty string `""` is used as a name for all upvalues.
+    #[link_name = "lua_getupvalue"]
+    pub fn getupvalue(state: lua_State, function: i32, n: i32) -> *const u8;
+    /// Sets the debugging hook function.
+    ///
+    /// `func` is the hook function. `mask` specifies on which events the hook will be called: it is formed by a bitwise or of the constants [`MASKCALL`], [`MASKRET`], [`MASKLINE`], and [`MASKCOUNT`].
+    /// The `count` argument is only meaningful when the mask includes [`MASKCOUNT`]; the hook is then called after the interpreter executes every `count` instructions.
+    ///
+    /// A hook is disabled by setting `mask` to zero.
+    #[link_name = "lua_sethook"]
+    pub fn sethook(state: lua_State, func: Option<lua_Hook>, mask: i32, count: i32) -> i32;
+    /// Returns the current hook function.
+    #[link_name = "lua_gethook"]
+    pub fn gethook(state: lua_State) -> Option<lua_Hook>;
+    /// Returns the current hook mask.
+    #[link_name = "lua_gethookmask"]
+    pub fn gethookmask(state: lua_State) -> i32;
+    /// Returns the current hook count.
+    #[link_name = "lua_gethookcount"]
+    pub fn gethookcount(state: lua_State) -> i32;
+    /// Returns the memory-allocation function of a given state. If `userdata` is not `NULL`, Lua stores in `*userdata` the opaque pointer passed to [`newstate_alloc`] (`lua_newstate`).
+    #[link_name = "lua_getallocf"]
+    pub fn getallocf(state: lua_State, userdata: *mut *mut c_void) -> lua_Alloc;
+    /// Changes the allocator function of a given state to `alloc` with user data `userdata`.
+    #[link_name = "lua_setallocf"]
+    pub fn setallocf(state: lua_State, alloc: lua_Alloc, userdata: *mut c_void);
+    /// Pops a key from the stack, and pushes a key-value pair from the table at the given index (the "next" pair after the given key).
+    /// If there are no more elements in the table, then [`next`] (`lua_next`) returns 0 (and pushes nothing).
+    ///
+    /// A typical traversal starts by pushing **nil** as the first key.
+    /// While traversing a table, do not call [`tolstring`] (`lua_tolstring`) directly on a key, unless you know that the key is actually a string.
+    #[link_name = "lua_next"]
+    pub fn next(state: lua_State, index: i32) -> i32;
     // #[link_name = "lua_concat"]
     // fn lua_concat(state: lua_State, index: i32);
 
@@ -433,6 +758,16 @@ extern "C" {
     #[link_name = "luaL_checkudata"]
     pub fn Lcheckudata(state: lua_State, index: i32, type_name: *const u8) -> *mut c_void;
 
+    /// Creates and returns a reference, in the table at index `table`, for the object at the top of the stack (and pops the object).
+    ///
+    /// A reference is a unique integer key. As long as you do not manually add integer keys into `table`, [`Lref`] ensures the uniqueness of the key it returns.
+    /// If the object at the top of the stack is **nil**, [`Lref`] returns the constant `LUA_REFNIL` (-1).
+    #[link_name = "luaL_ref"]
+    pub fn Lref(state: lua_State, table: i32) -> i32;
+    /// Releases reference `reference` from the table at index `table` (see [`Lref`]). The entry is removed from the table, so that the referred object can be collected.
+    #[link_name = "luaL_unref"]
+    pub fn Lunref(state: lua_State, table: i32, reference: i32);
+
     /// Pushes onto the stack a string identifying the current position of the control at level `level` in the call stack. Typically this string has the following format:
     /// ```text
     ///     chunkname:currentline:
@@ -442,12 +777,10 @@ extern "C" {
     /// This function is used to build a prefix for error messages.
     #[link_name = "luaL_where"]
     pub fn Lpush_where(state: lua_State, level: i32);
-    /// Raises an error. The error message format is given by `fmt` plus any extra arguments, following the same rules of [`lua_pushfstring`](https://www.lua.org/manual/5.1/manual.html#lua_pushfstring).
-    /// It also adds at the beginning of the message the file name and the line number where the error occurred, if this information is available.
-    ///
-    /// This function never returns, but it is an idiom to use it in C functions as `return luaL_error(args)`.
-    #[link_name = "luaL_error"]
-    pub fn Lerror(state: lua_State, fmt: *const u8, ...) -> !;
+    /// Creates and pushes a traceback of the stack `state1`. If `msg` is not `NULL` it is appended at the beginning of the traceback.
+    /// The `level` parameter tells at which level to start the traceback.
+    #[link_name = "luaL_traceback"]
+    pub fn Ltraceback(state: lua_State, state1: lua_State, msg: *const u8, level: i32);
 
     /// Loads a buffer as a Lua chunk. This function uses [`lua_load`](https://www.lua.org/manual/5.1/manual.html#lua_load) to load the chunk in the buffer pointed to by `buffer` with size `size`.
     ///
@@ -485,6 +818,85 @@ extern "C" {
     pub fn open_jit(state: lua_State) -> i32;
 }
 
+#[cfg(not(feature = "dynamic"))]
+extern "C-unwind" {
+    /// Raises an error. The error message format is given by `fmt` plus any extra arguments, following the same rules of [`lua_pushfstring`](https://www.lua.org/manual/5.1/manual.html#lua_pushfstring).
+    /// It also adds at the beginning of the message the file name and the line number where the error occurred, if this information is available.
+    ///
+    /// This function never returns, but it is an idiom to use it in C functions as `return luaL_error(args)`.
+    #[link_name = "luaL_error"]
+    pub fn Lerror(state: lua_State, fmt: *const u8, ...) -> !;
+}
+
+#[cfg(feature = "dynamic")]
+#[allow(non_upper_case_globals)]
+/// Raises an error. The error message format is given by `fmt` plus any extra arguments, following the same rules of [`lua_pushfstring`](https://www.lua.org/manual/5.1/manual.html#lua_pushfstring).
+/// It also adds at the beginning of the message the file name and the line number where the error occurred, if this information is available.
+///
+/// This function never returns, but it is an idiom to use it in C functions as `return luaL_error(args)`.
+pub static Lerror: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(lua_State, *const u8, ...) -> !,
+> = std::sync::LazyLock::new(dynamic::error_function);
+
+/// Converts a relative stack index into an absolute one. Pseudo-indices are returned as they are.
+pub(crate) unsafe fn absindex(state: lua_State, index: i32) -> i32 {
+    if index < 0 && index > REGISTRYINDEX {
+        gettop(state) + index + 1
+    } else {
+        index
+    }
+}
+
+/// Returns an address unique to `T`, for use as a lightuserdata key.
+pub(crate) fn type_key<T>() -> *const c_void
+where
+    T: 'static,
+{
+    static KEYS: std::sync::Mutex<Vec<(std::any::TypeId, usize)>> =
+        std::sync::Mutex::new(Vec::new());
+    let mut keys = KEYS.lock().unwrap_or_else(|err| err.into_inner());
+    let id = std::any::TypeId::of::<T>();
+    let key = match keys.iter().find(|(other, _)| *other == id) {
+        Some((_, key)) => *key,
+        None => {
+            // Any unique address will do.
+            let key = Box::leak(Box::new(0u8)) as *const u8 as usize;
+            keys.push((id, key));
+            key
+        }
+    };
+    key as *const c_void
+}
+
+/// Pops the value on the top of the stack and returns it as an error message.
+///
+/// # Safety
+/// The stack of `state` must not be empty.
+pub unsafe fn pop_message(state: lua_State) -> String {
+    let mut len = 0;
+    let message = tolstring(state, -1, &mut len);
+    let message = if message.is_null() {
+        String::from("(error object is not a string)")
+    } else {
+        String::from_utf8_lossy(std::slice::from_raw_parts(message, len)).into_owned()
+    };
+    pop!(state, 1);
+    message
+}
+
+/// Message handler for [`pcall`] that turns the error message into a message with a traceback appended.
+///
+/// Non-string error objects are left untouched.
+///
+/// # Safety
+/// Meant to be called by Lua, as a message handler; `state` must be a valid Lua state with the error object at index 1.
+pub unsafe extern "C" fn traceback(state: lua_State) -> i32 {
+    if isstring(state, 1) {
+        Ltraceback(state, state, tolstring(state, 1, &mut 0), 1);
+    }
+    1
+}
+
 /// Pushes rust function/closure to lua stack.
 /// # Example
 /// ```
@@ -516,28 +928,27 @@ pub unsafe fn pushfunction<FUNC>(state: lua_State, callback: FUNC)
 where
     FUNC: 'static + FnMut(lua_State) -> Result,
 {
-    unsafe extern "C" fn call_callback<FUNC>(state: lua_State) -> i32
+    unsafe extern "C-unwind" fn call_callback<FUNC>(state: lua_State) -> i32
     where
         FUNC: 'static + FnMut(lua_State) -> Result,
     {
+        // A panic can't unwind through Lua, so the wrong thread gets a Lua error instead.
+        #[cfg(debug_assertions)]
+        if !is_owner_thread(state) {
+            raise(
+                state,
+                "function called from a thread that does not own the state".into(),
+            );
+        }
+        // Zero-sized callbacks have no userdata, but still need a non-null pointer.
         let callback_ptr = if std::mem::size_of::<FUNC>() > 0 {
-            touserdata(state, upvalueindex!(1))
+            touserdata(state, upvalueindex!(1)).cast::<FUNC>()
         } else {
-            null_mut()
+            std::ptr::NonNull::<FUNC>::dangling().as_ptr()
         };
-        match (&mut *callback_ptr.cast::<FUNC>())(state) {
+        match (&mut *callback_ptr)(state) {
             Ok(nrets) => nrets,
-            Err(err) => {
-                let error_str = err.to_string();
-                std::mem::drop(err);
-                pushlstring(
-                    state,
-                    error_str.as_bytes().as_ptr(),
-                    error_str.as_bytes().len(),
-                );
-                std::mem::drop(error_str);
-                error(state);
-            }
+            Err(err) => raise(state, err),
         }
     }
 
@@ -555,8 +966,47 @@ where
         pushcclosure(state, cleanup_callback::<FUNC>, 0);
         setfield(state, -2, cstr!("__gc"));
         setmetatable(state, -2);
-        pushcclosure(state, call_callback::<FUNC>, 1);
+        pushcclosure(state, cfunction(call_callback::<FUNC>), 1);
     } else {
-        pushcclosure(state, call_callback::<FUNC>, 0);
+        pushcclosure(state, cfunction(call_callback::<FUNC>), 0);
+    }
+}
+
+/// Raises `err` as a Lua error with its message.
+pub(crate) unsafe fn raise(state: lua_State, err: Box<dyn std::error::Error>) -> ! {
+    let error_str = err.to_string();
+    std::mem::drop(err);
+    pushlstring(state, error_str.as_ptr(), error_str.len());
+    std::mem::drop(error_str);
+    error(state);
+}
+
+/// Calls `callback` in protected mode with the whole stack as its arguments, the way Lua calls a function pushed with [`pushfunction`].
+///
+/// Returns the number of results, which are left on the stack, or `None` with the error object left on the stack.
+/// Lets callers clean up after a Lua error before passing it on.
+pub(crate) unsafe fn pcall_callback<FUNC>(state: lua_State, callback: &mut FUNC) -> Option<i32>
+where
+    FUNC: FnMut(lua_State) -> Result,
+{
+    unsafe extern "C-unwind" fn call_callback<FUNC>(state: lua_State) -> i32
+    where
+        FUNC: FnMut(lua_State) -> Result,
+    {
+        let callback = &mut *touserdata(state, upvalueindex!(1)).cast::<FUNC>();
+        match callback(state) {
+            Ok(nrets) => nrets,
+            Err(err) => raise(state, err),
+        }
+    }
+
+    let nargs = gettop(state);
+    Lcheckstack(state, 2, cstr!("too many arguments"));
+    pushlightuserdata(state, callback as *mut FUNC as *const c_void);
+    pushcclosure(state, cfunction(call_callback::<FUNC>), 1);
+    insert(state, 1);
+    match pcall(state, nargs, -1, 0) {
+        Status::Ok => Some(gettop(state)),
+        _ => None,
     }
 }
@@ -0,0 +1,118 @@
+#[cfg(feature = "sha256")]
+use std::collections::HashSet;
+
+#[cfg(feature = "sha256")]
+use sha2::{Digest, Sha256};
+
+use crate::{bytecode::is_bytecode, exec::load_buffer, lua_State, LError, LoadMode};
+
+/// Decides which chunks [`load_buffer_checked`] and [`loadx_checked`] accept.
+///
+/// Source code is accepted by every policy. Binary chunks can bypass the bytecode verifier and escape any sandbox, so they are only accepted when the policy explicitly vouches for them.
+#[derive(Clone, Debug)]
+pub enum LoadPolicy {
+    /// Only source code.
+    TextOnly,
+    /// Source code, and binary chunks whose SHA-256 (see [`sha256`]) is in the set.
+    #[cfg(feature = "sha256")]
+    HashAllowlist(HashSet<[u8; 32]>),
+    /// Source code, and binary chunks with a valid detached ed25519 signature from one of the keys.
+    #[cfg(feature = "ed25519")]
+    Signed(Vec<ed25519_dalek::VerifyingKey>),
+    /// Anything, like plain [`loadx`](crate::loadx).
+    Any,
+}
+
+impl LoadPolicy {
+    /// Checks `chunk` against the policy and returns the mode it has to be loaded with.
+    ///
+    /// `signature` is only looked at by [`LoadPolicy::Signed`].
+    pub fn check(
+        &self,
+        chunk: &[u8],
+        signature: Option<&[u8]>,
+    ) -> std::result::Result<LoadMode, LError> {
+        if !is_bytecode(chunk) {
+            return Ok(LoadMode::Text);
+        }
+        match self {
+            LoadPolicy::TextOnly => Err(LError::Rejected(String::from(
+                "binary chunks are not allowed",
+            ))),
+            #[cfg(feature = "sha256")]
+            LoadPolicy::HashAllowlist(hashes) => {
+                if hashes.contains(&sha256(chunk)) {
+                    Ok(LoadMode::Binary)
+                } else {
+                    Err(LError::Rejected(String::from(
+                        "binary chunk is not in the allowlist",
+                    )))
+                }
+            }
+            #[cfg(feature = "ed25519")]
+            LoadPolicy::Signed(keys) => {
+                let signature = signature
+                    .ok_or_else(|| LError::Rejected(String::from("binary chunk is not signed")))?;
+                let signature = ed25519_dalek::Signature::from_slice(signature)
+                    .map_err(|_| LError::Rejected(String::from("malformed signature")))?;
+                if keys
+                    .iter()
+                    .any(|key| key.verify_strict(chunk, &signature).is_ok())
+                {
+                    Ok(LoadMode::Binary)
+                } else {
+                    Err(LError::Rejected(String::from(
+                        "binary chunk has no valid signature",
+                    )))
+                }
+            }
+            LoadPolicy::Any => {
+                let _ = signature;
+                Ok(LoadMode::Binary)
+            }
+        }
+    }
+}
+
+/// Returns the SHA-256 of `chunk`, as used by [`LoadPolicy::HashAllowlist`].
+#[cfg(feature = "sha256")]
+pub fn sha256(chunk: &[u8]) -> [u8; 32] {
+    Sha256::digest(chunk).into()
+}
+
+/// Loads `buffer` as a Lua chunk if `policy` allows it.
+///
+/// Accepted chunks are loaded with the matching mode only, so a chunk that passed as text can't be loaded as bytecode.
+/// On success the loaded function is pushed onto the stack.
+///
+/// # Safety
+/// `chunk_name` must be a NUL-terminated string, and `state` must have room for one more stack slot.
+pub unsafe fn load_buffer_checked(
+    state: lua_State,
+    buffer: &[u8],
+    chunk_name: *const u8,
+    policy: &LoadPolicy,
+    signature: Option<&[u8]>,
+) -> std::result::Result<(), LError> {
+    let mode = policy.check(buffer, signature)?;
+    load_buffer(state, buffer, chunk_name, mode)
+}
+
+/// Reads the whole chunk from `reader` and loads it like [`load_buffer_checked`].
+///
+/// # Safety
+/// Same as [`load_buffer_checked`].
+pub unsafe fn loadx_checked<READER>(
+    state: lua_State,
+    reader: &mut READER,
+    chunk_name: *const u8,
+    policy: &LoadPolicy,
+    signature: Option<&[u8]>,
+) -> std::result::Result<(), LError>
+where
+    READER: std::io::Read,
+{
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).map_err(LError::IoError)?;
+    load_buffer_checked(state, &buffer, chunk_name, policy, signature)
+}
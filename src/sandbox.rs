@@ -0,0 +1,245 @@
+//! Restricted environments for untrusted code.
+//!
+//! [`SandboxBuilder`] assembles an environment table out of a whitelist of the state's globals.
+//! Anything that is not whitelisted (`debug`, `os`, `io`, `jit`, `load*`, `dofile`, `require`, `setfenv`, `getfenv`, `rawset`, ...) is simply not there.
+//! Whitelisted libraries are copied, so sandboxed code can't modify the real ones.
+//! ```no_run
+//! # use lua_shared::{self as lua, cstr, sandbox};
+//! # unsafe fn open(state: lua::lua_State) -> Result<(), lua::LError> {
+//! sandbox::SandboxBuilder::new()
+//!     .function("log", |state| {
+//!         let mut len = 0;
+//!         lua::Lchecklstring(state, 1, &mut len);
+//!         Ok(0)
+//!     })
+//!     .build(state);
+//! sandbox::load(state, &mut "log('hi')".as_bytes(), cstr!("=plugin"), -1)?;
+//! lua::call(state, 0, 0);
+//! lua::pop!(state, 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    absindex, createtable, cstr, get_type, gettop, loadx, lua_State, next, pop, pushfunction,
+    pushlstring, pushnil, pushvalue, rawget, rawset, setfenv, setfield, LError, Result,
+    GLOBALSINDEX, TNIL, TTABLE,
+};
+
+/// Base library functions that are safe to expose.
+pub const SAFE_GLOBALS: &[&str] = &[
+    "_VERSION",
+    "assert",
+    "error",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "xpcall",
+];
+
+/// Functions of the `string` library that are safe to expose. `string.dump` is left out.
+pub const SAFE_STRING: &[&str] = &[
+    "byte", "char", "find", "format", "gmatch", "gsub", "len", "lower", "match", "rep", "reverse",
+    "sub", "upper",
+];
+
+/// Functions of the `table` library that are safe to expose.
+pub const SAFE_TABLE: &[&str] = &["concat", "insert", "maxn", "remove", "sort"];
+
+enum Entry {
+    Global(String),
+    Library(String, Option<Vec<String>>),
+    Capability(String, Box<dyn FnOnce(lua_State)>),
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        match self {
+            Entry::Global(name) | Entry::Library(name, _) | Entry::Capability(name, _) => name,
+        }
+    }
+}
+
+/// Builder for sandbox environment tables.
+pub struct SandboxBuilder {
+    entries: Vec<Entry>,
+}
+
+impl Default for SandboxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SandboxBuilder {
+    /// Creates a builder with the default whitelist: [`SAFE_GLOBALS`], [`SAFE_STRING`], [`SAFE_TABLE`], `math` and `coroutine`.
+    pub fn new() -> Self {
+        Self::empty()
+            .allow_all(SAFE_GLOBALS)
+            .allow_library("string", Some(SAFE_STRING))
+            .allow_library("table", Some(SAFE_TABLE))
+            .allow_library("math", None)
+            .allow_library("coroutine", None)
+    }
+
+    /// Creates a builder that exposes nothing.
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    fn add(mut self, entry: Entry) -> Self {
+        self.entries.retain(|other| other.name() != entry.name());
+        self.entries.push(entry);
+        self
+    }
+
+    /// Exposes the global `name` as is.
+    pub fn allow(self, name: &str) -> Self {
+        self.add(Entry::Global(name.to_string()))
+    }
+
+    /// Exposes every global in `names` as is.
+    pub fn allow_all(self, names: &[&str]) -> Self {
+        names.iter().fold(self, |builder, name| builder.allow(name))
+    }
+
+    /// Exposes a copy of the global table `name`, limited to `fields` if they are given.
+    pub fn allow_library(self, name: &str, fields: Option<&[&str]>) -> Self {
+        self.add(Entry::Library(
+            name.to_string(),
+            fields.map(|fields| fields.iter().map(|field| field.to_string()).collect()),
+        ))
+    }
+
+    /// Removes `name` from the environment.
+    pub fn deny(mut self, name: &str) -> Self {
+        self.entries.retain(|entry| entry.name() != name);
+        self
+    }
+
+    /// Exposes a Rust function as `name`. See [`pushfunction`].
+    pub fn function<FUNC>(self, name: &str, callback: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State) -> Result,
+    {
+        self.add(Entry::Capability(
+            name.to_string(),
+            Box::new(move |state| unsafe { pushfunction(state, callback) }),
+        ))
+    }
+
+    /// Exposes the value pushed by `push` as `name`. `push` must push exactly one value.
+    pub fn value<FUNC>(self, name: &str, push: FUNC) -> Self
+    where
+        FUNC: 'static + FnOnce(lua_State),
+    {
+        self.add(Entry::Capability(name.to_string(), Box::new(push)))
+    }
+
+    /// Builds the environment table and pushes it onto the stack.
+    ///
+    /// Whitelisted names are looked up in the globals of `state`, so the libraries must be opened beforehand. Missing ones are skipped.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state with room for a few more stack slots, and capabilities must push exactly one value each.
+    pub unsafe fn build(self, state: lua_State) {
+        createtable(state, 0, self.entries.len() as i32 + 1);
+        let env = gettop(state);
+        for entry in self.entries {
+            match entry {
+                Entry::Global(name) => {
+                    pushlstring(state, name.as_ptr(), name.len());
+                    pushlstring(state, name.as_ptr(), name.len());
+                    rawget(state, GLOBALSINDEX);
+                    rawset(state, env);
+                }
+                Entry::Library(name, fields) => {
+                    pushlstring(state, name.as_ptr(), name.len());
+                    rawget(state, GLOBALSINDEX);
+                    if get_type(state, -1) != TTABLE {
+                        pop!(state, 1);
+                        continue;
+                    }
+                    let library = gettop(state);
+                    pushlstring(state, name.as_ptr(), name.len());
+                    createtable(state, 0, 0);
+                    match fields {
+                        Some(fields) => {
+                            for field in fields {
+                                pushlstring(state, field.as_ptr(), field.len());
+                                pushvalue(state, -1);
+                                rawget(state, library);
+                                rawset(state, -3);
+                            }
+                        }
+                        None => {
+                            pushnil(state);
+                            while next(state, library) != 0 {
+                                pushvalue(state, -2);
+                                pushvalue(state, -2);
+                                rawset(state, -5);
+                                pop!(state, 1);
+                            }
+                        }
+                    }
+                    rawset(state, env);
+                    pop!(state, 1);
+                }
+                Entry::Capability(name, push) => {
+                    pushlstring(state, name.as_ptr(), name.len());
+                    push(state);
+                    if get_type(state, -1) == TNIL {
+                        pop!(state, 2);
+                        continue;
+                    }
+                    rawset(state, env);
+                }
+            }
+        }
+        pushvalue(state, env);
+        setfield(state, env, cstr!("_G"));
+    }
+}
+
+/// Sets the table at `env` as the environment of the function at `func`.
+///
+/// # Safety
+/// `func` must be a Lua function and `env` a table, both valid indices in `state`.
+pub unsafe fn apply(state: lua_State, func: i32, env: i32) {
+    let func = absindex(state, func);
+    pushvalue(state, env);
+    setfenv(state, func);
+}
+
+/// Loads a text chunk (binary chunks are refused) and sets the table at `env` as its environment.
+///
+/// On success the loaded function is pushed onto the stack.
+///
+/// # Safety
+/// `chunk_name` must be a NUL-terminated string and `env` a valid index of a table in `state`.
+pub unsafe fn load<READER>(
+    state: lua_State,
+    reader: &mut READER,
+    chunk_name: *const u8,
+    env: i32,
+) -> std::result::Result<(), LError>
+where
+    READER: std::io::Read,
+{
+    let env = absindex(state, env);
+    loadx(state, reader, chunk_name, cstr!("t"))?;
+    apply(state, -1, env);
+    Ok(())
+}
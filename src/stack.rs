@@ -0,0 +1,153 @@
+use std::fmt;
+
+use crate::{
+    cstr, get_type, getmetatable, gettop, iscfunction, lua_State, objlen, pop, pushstring, rawget,
+    toboolean, tolstring, tonumber, topointer, typename, TBOOLEAN, TFUNCTION, TNIL, TNUMBER,
+    TSTRING, TTABLE,
+};
+
+/// Strings longer than this are cut in previews.
+const PREVIEW_LENGTH: usize = 40;
+
+/// One slot of a [`StackDump`].
+#[derive(Clone, Debug)]
+pub struct StackSlot {
+    /// Absolute index, from `1` at the bottom.
+    pub index: i32,
+    /// Relative index, from `-1` at the top.
+    pub relative: i32,
+    pub type_name: String,
+    /// Short, human readable form of the value.
+    pub preview: String,
+    /// `__name` or `MetaName` of the metatable, if the value has one.
+    pub metatable: Option<String>,
+}
+
+/// Snapshot of the whole stack, returned by [`debug_stack`].
+///
+/// Displays one slot per line, bottom first:
+/// ```text
+/// stack (3 values)
+///   [1 | -3] number    42
+///   [2 | -2] string    "hello"
+///   [3 | -1] userdata  0x7f3a5c0012a8 <Entity>
+/// ```
+#[derive(Clone, Debug)]
+pub struct StackDump {
+    pub slots: Vec<StackSlot>,
+}
+
+impl fmt::Display for StackDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.slots.len() {
+            0 => return write!(f, "stack (empty)"),
+            1 => write!(f, "stack (1 value)")?,
+            len => write!(f, "stack ({} values)", len)?,
+        }
+        for slot in &self.slots {
+            write!(
+                f,
+                "\n  [{} | {}] {:<9} {}",
+                slot.index, slot.relative, slot.type_name, slot.preview
+            )?;
+            if let Some(name) = &slot.metatable {
+                write!(f, " <{}>", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe fn string_at(state: lua_State, index: i32) -> Option<Vec<u8>> {
+    let mut len = 0;
+    let ptr = tolstring(state, index, &mut len);
+    match ptr.is_null() {
+        true => None,
+        false => Some(std::slice::from_raw_parts(ptr, len).to_vec()),
+    }
+}
+
+unsafe fn preview(state: lua_State, index: i32) -> String {
+    match get_type(state, index) {
+        TNIL => String::from("nil"),
+        TBOOLEAN => toboolean(state, index).to_string(),
+        TNUMBER => tonumber(state, index).to_string(),
+        TSTRING => {
+            let bytes = string_at(state, index).unwrap_or_default();
+            let string = String::from_utf8_lossy(&bytes);
+            match string.char_indices().nth(PREVIEW_LENGTH) {
+                Some((cut, _)) => format!("{:?}... ({} bytes)", &string[..cut], bytes.len()),
+                None => format!("{:?}", string),
+            }
+        }
+        TTABLE => format!("{:p} (#{})", topointer(state, index), objlen(state, index)),
+        TFUNCTION if iscfunction(state, index) => format!("{:p} (C)", topointer(state, index)),
+        _ => format!("{:p}", topointer(state, index)),
+    }
+}
+
+/// Reads the `__name` or, like the game's own types, `MetaName` field of the value's metatable.
+unsafe fn metatable_name(state: lua_State, index: i32) -> Option<String> {
+    if getmetatable(state, index) == 0 {
+        return None;
+    }
+    let mut name = None;
+    for field in [cstr!("__name"), cstr!("MetaName")] {
+        pushstring(state, field);
+        rawget(state, -2);
+        if get_type(state, -1) == TSTRING {
+            name = string_at(state, -1).map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+        }
+        pop!(state, 1);
+        if name.is_some() {
+            break;
+        }
+    }
+    pop!(state, 1);
+    name
+}
+
+/// Captures every slot of the stack, from `1` to [`gettop`], without changing it.
+///
+/// Needs two free stack slots to read metatables.
+///
+/// # Safety
+/// `state` must be a valid Lua state with those two slots to spare.
+pub unsafe fn debug_stack(state: lua_State) -> StackDump {
+    let top = gettop(state);
+    let slots = (1..=top)
+        .map(|index| StackSlot {
+            index,
+            relative: index - top - 1,
+            type_name: std::ffi::CStr::from_ptr(typename(state, get_type(state, index)).cast())
+                .to_string_lossy()
+                .into_owned(),
+            preview: preview(state, index),
+            metatable: metatable_name(state, index),
+        })
+        .collect();
+    StackDump { slots }
+}
+
+/// Used by [`lua_stack_trace`](crate::lua_stack_trace).
+#[doc(hidden)]
+pub fn log_stack(file: &str, line: u32, dump: StackDump) {
+    #[cfg(feature = "log")]
+    log::debug!("{}:{}: {}", file, line, dump);
+    #[cfg(not(feature = "log"))]
+    eprintln!("{}:{}: {}", file, line, dump);
+}
+
+/// Prints [`debug_stack`] prefixed with the current file and line.
+///
+/// Goes through `log::debug!` with the `log` feature and to stderr otherwise. Must be used where [`debug_stack`] can be called (in an `unsafe` context).
+/// ```ignore
+/// lua::pushnumber(state, 1.0);
+/// lua::lua_stack_trace!(state); // src/lib.rs:12: stack (1 value) ...
+/// ```
+#[macro_export]
+macro_rules! lua_stack_trace {
+    ($L:expr) => {
+        $crate::log_stack(file!(), line!(), $crate::debug_stack($L))
+    };
+}
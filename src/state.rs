@@ -0,0 +1,103 @@
+use std::{
+    ffi::c_void,
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    cstr, getfield, lua_State, pop, pushlightuserdata, setfield, touserdata, REGISTRYINDEX,
+};
+
+/// Registry field holding the id of the thread that opened the state.
+const REGISTRY_FIELD: *const u8 = cstr!("lua_shared.owner");
+
+/// Returns an id for the current thread that is never handed out again, unlike [`std::thread::ThreadId`] it fits into a lightuserdata.
+fn thread_id() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    thread_local! {
+        static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+/// Thread-bound wrapper around [`lua_State`].
+///
+/// Unlike the raw pointer it can't be turned into an integer and sent to another thread without someone noticing: it is `!Send + !Sync` and, in debug builds, [`State::as_ptr`] panics when it is called outside of the thread that called [`State::open`] for this state.
+///
+/// It is `#[repr(transparent)]`, so it can be used directly in `extern "C"` signatures:
+/// ```no_run
+/// # use lua_shared::State;
+/// #[no_mangle]
+/// unsafe extern "C" fn gmod13_open(state: State) -> i32 {
+///     let state = State::open(state.as_ptr());
+///     0
+/// }
+/// ```
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct State {
+    ptr: lua_State,
+    _marker: PhantomData<*mut ()>,
+}
+
+impl State {
+    /// Wraps `state` and records the current thread as its owner in the state's registry. Meant to be called from `gmod13_open`.
+    ///
+    /// The owner is shared by all coroutines of the state. Calling it again from another thread hands the state over.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state with room for one more stack slot.
+    pub unsafe fn open(state: lua_State) -> Self {
+        pushlightuserdata(state, thread_id() as *const c_void);
+        setfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+        Self::from_raw(state)
+    }
+
+    /// Wraps `state` without recording anything.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state that outlives every copy of the wrapper.
+    pub unsafe fn from_raw(state: lua_State) -> Self {
+        Self {
+            ptr: state,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the raw state, checking the calling thread in debug builds.
+    #[inline]
+    #[track_caller]
+    pub fn as_ptr(self) -> lua_State {
+        unsafe { check_thread(self.ptr) };
+        self.ptr
+    }
+}
+
+/// Returns `true` if the current thread opened `state` through [`State::open`], or if nobody did.
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for one more stack slot.
+pub unsafe fn is_owner_thread(state: lua_State) -> bool {
+    getfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+    let owner = touserdata(state, -1) as usize;
+    pop!(state, 1);
+    owner == 0 || owner == thread_id()
+}
+
+/// Panics if `state` was opened through [`State::open`] on another thread. Does nothing in release builds.
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for one more stack slot.
+#[inline]
+#[track_caller]
+pub unsafe fn check_thread(state: lua_State) {
+    #[cfg(debug_assertions)]
+    if !is_owner_thread(state) {
+        panic!(
+            "lua_State used from {:?}, which did not open it",
+            std::thread::current().id()
+        );
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = state;
+}
@@ -0,0 +1,326 @@
+//! Running module tests against a real Lua state (`testing` feature).
+//!
+//! Tests need a Lua that can run outside the game, so this is usually combined with `luajit-vendored`, `luajit-system` or `dynamic`:
+//! ```toml
+//! [dev-dependencies]
+//! lua-shared = { version = "0.1", features = ["testing", "luajit-vendored"] }
+//! ```
+//! [`lua_test`] turns a function into a test that gets a fresh state with the standard libraries opened and the crate's `gmod13_open` already run:
+//! ```ignore
+//! use lua_shared::{self as lua, testing::lua_test};
+//!
+//! #[lua_test]
+//! unsafe fn adds(state: lua::lua_State) -> Result<(), lua::LError> {
+//!     let sum: f64 = lua::exec(state, "return mymodule.add(1, 2)", "test")?;
+//!     assert_eq!(sum, 3.0);
+//!     Ok(())
+//! }
+//! ```
+//! [`run_lua_files`] runs every `*_test.lua` in a directory, with `describe`, `it`, `assert_eq` and `assert_ne` available:
+//! ```lua
+//! describe("mymodule.add", function()
+//!     it("adds numbers", function()
+//!         assert_eq(mymodule.add(1, 2), 3)
+//!     end)
+//! end)
+//! ```
+//! All tests run on one dedicated thread, one at a time, so modules that record their Lua thread or keep thread-local state behave like they do in the game.
+//! Lua errors must not escape into Rust: use [`exec`](crate::exec) or `pcall` rather than raw calls that can raise errors.
+
+use std::{
+    any::Any,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex, OnceLock},
+};
+
+pub use lua_shared_macros::lua_test;
+
+use crate::{
+    close, exec, exec_file, gettop, lua_State, newstate, pcall, pop_message, pushcclosure, remove,
+    traceback, Lopenlibs, State, Status,
+};
+
+/// Signature of `gmod13_open`.
+pub type OpenFunction = unsafe extern "C" fn(state: lua_State) -> i32;
+
+/// Return types accepted from [`lua_test`] functions.
+pub trait TestResult {
+    fn into_result(self) -> std::result::Result<(), String>;
+}
+
+impl TestResult for () {
+    fn into_result(self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E> TestResult for std::result::Result<(), E>
+where
+    E: std::fmt::Display,
+{
+    fn into_result(self) -> std::result::Result<(), String> {
+        self.map_err(|err| err.to_string())
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs `job` on the Lua test thread and returns its result, or its panic.
+fn on_lua_thread<JOB, RET>(job: JOB) -> std::thread::Result<RET>
+where
+    JOB: 'static + Send + FnOnce() -> RET,
+    RET: 'static + Send,
+{
+    static JOBS: OnceLock<Mutex<mpsc::Sender<Job>>> = OnceLock::new();
+    let jobs = JOBS.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name(String::from("lua-test"))
+            .spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            })
+            .expect("failed to spawn the Lua test thread");
+        Mutex::new(sender)
+    });
+    let (sender, receiver) = mpsc::channel();
+    let job: Job = Box::new(move || {
+        let _ = sender.send(std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)));
+    });
+    jobs.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .send(job)
+        .expect("the Lua test thread is gone");
+    receiver.recv().expect("the Lua test thread is gone")
+}
+
+/// Closes the state when dropped, also when the test panics.
+struct Guard(lua_State);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe { close(self.0) };
+    }
+}
+
+/// Creates a state with the standard libraries and runs `open` in it.
+unsafe fn open_state(open: Option<OpenFunction>) -> std::result::Result<Guard, String> {
+    let state = Guard(newstate());
+    if state.0.is_null() {
+        return Err(String::from("failed to create a Lua state"));
+    }
+    State::open(state.0);
+    Lopenlibs(state.0);
+    if let Some(open) = open {
+        let base = gettop(state.0) + 1;
+        pushcclosure(state.0, traceback, 0);
+        pushcclosure(state.0, open, 0);
+        let status = pcall(state.0, 0, 0, base);
+        if status != Status::Ok {
+            return Err(format!("gmod13_open failed: {}", pop_message(state.0)));
+        }
+        remove(state.0, base);
+    }
+    Ok(state)
+}
+
+fn resume(payload: Box<dyn Any + Send>) -> ! {
+    std::panic::resume_unwind(payload)
+}
+
+/// Runs `test` on a fresh state and panics if it fails. This is what [`lua_test`] expands to.
+///
+/// `open` is called first under `pcall`; the state is closed afterwards.
+pub fn run_test<FUNC, RET>(open: Option<OpenFunction>, test: FUNC)
+where
+    FUNC: 'static + Send + FnOnce(lua_State) -> RET,
+    RET: TestResult,
+{
+    let outcome = on_lua_thread(move || unsafe {
+        let state = open_state(open)?;
+        test(state.0).into_result()
+    });
+    match outcome {
+        Ok(Ok(())) => {}
+        Ok(Err(message)) => panic!("{}", message),
+        Err(payload) => resume(payload),
+    }
+}
+
+const PRELUDE: &str = r#"
+local results = {}
+local path = {}
+local traceback = debug and debug.traceback or tostring
+
+local function format(value)
+    if type(value) == "string" then
+        return string.format("%q", value)
+    end
+    return tostring(value)
+end
+
+local function equal(a, b, seen)
+    if a == b then
+        return true
+    end
+    if type(a) ~= "table" or type(b) ~= "table" then
+        return false
+    end
+    seen = seen or {}
+    if seen[a] == b then
+        return true
+    end
+    seen[a] = b
+    for key, value in pairs(a) do
+        if not equal(value, b[key], seen) then
+            return false
+        end
+    end
+    for key in pairs(b) do
+        if a[key] == nil then
+            return false
+        end
+    end
+    return true
+end
+
+function describe(name, body)
+    path[#path + 1] = tostring(name)
+    local ok, err = xpcall(body, traceback)
+    if not ok then
+        results[#results + 1] = { table.concat(path, " > "), false, tostring(err) }
+    end
+    path[#path] = nil
+end
+
+function it(name, body)
+    path[#path + 1] = tostring(name)
+    local ok, err = xpcall(body, traceback)
+    results[#results + 1] = { table.concat(path, " > "), ok, not ok and tostring(err) or nil }
+    path[#path] = nil
+end
+
+function assert_eq(actual, expected, message)
+    if not equal(actual, expected) then
+        error((message and message .. ": " or "") .. "expected " .. format(expected) .. ", got " .. format(actual), 2)
+    end
+end
+
+function assert_ne(actual, unexpected, message)
+    if equal(actual, unexpected) then
+        error((message and message .. ": " or "") .. "expected anything but " .. format(unexpected), 2)
+    end
+end
+
+function __lua_test_count()
+    return #results
+end
+
+function __lua_test_result(index)
+    local result = results[index]
+    return result[1], result[2], result[3]
+end
+"#;
+
+/// Collects the `*_test.lua` files under `directory`.
+fn collect(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with("_test.lua"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Runs one test file and returns `(name, error)` for every case.
+unsafe fn run_file(
+    open: Option<OpenFunction>,
+    path: &Path,
+) -> Vec<(String, std::result::Result<(), String>)> {
+    let file = path.display().to_string();
+    let state = match open_state(open) {
+        Ok(state) => state,
+        Err(message) => return vec![(file, Err(message))],
+    };
+    if let Err(err) = exec::<()>(state.0, PRELUDE, "=lua_test") {
+        return vec![(file, Err(err.to_string()))];
+    }
+    if let Err(err) = exec_file::<(), _>(state.0, path) {
+        return vec![(file, Err(err.to_string()))];
+    }
+    let count: usize = match exec(state.0, "return __lua_test_count()", "=lua_test") {
+        Ok(count) => count,
+        Err(err) => return vec![(file, Err(err.to_string()))],
+    };
+    if count == 0 {
+        return vec![(file, Ok(()))];
+    }
+    (1..=count)
+        .map(|index| {
+            let code = format!("return __lua_test_result({})", index);
+            match exec::<(String, bool, Option<String>)>(state.0, &code, "=lua_test") {
+                Ok((name, true, _)) => (format!("{} :: {}", file, name), Ok(())),
+                Ok((name, false, message)) => (
+                    format!("{} :: {}", file, name),
+                    Err(message.unwrap_or_default()),
+                ),
+                Err(err) => (file.clone(), Err(err.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Runs every `*_test.lua` file under `directory`, each in a fresh state set up like for [`lua_test`], and panics if any case fails.
+///
+/// Every case is printed like a libtest line, and the panic message lists the failures with their tracebacks.
+/// ```ignore
+/// #[test]
+/// fn lua_files() {
+///     lua_shared::testing::run_lua_files("tests/lua", Some(crate::gmod13_open));
+/// }
+/// ```
+pub fn run_lua_files<PATH>(directory: PATH, open: Option<OpenFunction>)
+where
+    PATH: AsRef<Path>,
+{
+    let directory = directory.as_ref();
+    let mut files = Vec::new();
+    if let Err(err) = collect(directory, &mut files) {
+        panic!("{}: {}", directory.display(), err);
+    }
+    files.sort();
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for path in files {
+        let results = match on_lua_thread(move || unsafe { run_file(open, &path) }) {
+            Ok(results) => results,
+            Err(payload) => resume(payload),
+        };
+        for (name, result) in results {
+            match result {
+                Ok(()) => {
+                    println!("test {} ... ok", name);
+                    passed += 1;
+                }
+                Err(message) => {
+                    println!("test {} ... FAILED", name);
+                    failures.push((name, message));
+                }
+            }
+        }
+    }
+    if !failures.is_empty() {
+        let mut message = format!("{} passed, {} failed\n", passed, failures.len());
+        for (name, failure) in &failures {
+            message.push_str(&format!("\n---- {} ----\n{}\n", name, failure));
+        }
+        panic!("{}", message);
+    }
+}
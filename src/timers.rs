@@ -0,0 +1,241 @@
+//! Rust-side timers that run on the Lua thread.
+//!
+//! Timers are stored per thread and are only ever fired from [`pump`], so callbacks always get a valid [`lua_State`].
+//! Call [`pump`] yourself or push [`pushpump`] into a `Think` hook:
+//! ```no_run
+//! # use lua_shared::{self as lua, cstr, timers};
+//! # use std::time::Duration;
+//! # unsafe fn open(state: lua::lua_State) {
+//! lua::getglobal!(state, cstr!("hook"));
+//! lua::getfield(state, -1, cstr!("Add"));
+//! lua::pushstring(state, cstr!("Think"));
+//! lua::pushstring(state, cstr!("my_module_timers"));
+//! timers::pushpump(state);
+//! lua::call(state, 3, 0);
+//! lua::pop!(state, 1);
+//!
+//! let handle = timers::every(Duration::from_secs(1), |_| {
+//!     println!("tick");
+//!     Ok(())
+//! });
+//! handle.cancel();
+//! # }
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    ffi::c_void,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cfunction, check_thread, cstr, get_type, getfield, gettop, lua_State, pcall, pop, pushcclosure,
+    pushlightuserdata, pushlstring, raise, settop, tolstring, touserdata, traceback, upvalueindex,
+    Status, GLOBALSINDEX, TFUNCTION,
+};
+
+pub type TimerResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+type Callback = Box<dyn FnMut(lua_State) -> TimerResult>;
+
+struct Timer {
+    interval: Option<Duration>,
+    cancelled: Rc<Cell<bool>>,
+    callback: Callback,
+}
+
+#[derive(Default)]
+struct TimerWheel {
+    next_id: u64,
+    timers: BTreeMap<(Instant, u64), Timer>,
+}
+
+thread_local! {
+    static TIMERS: RefCell<TimerWheel> = RefCell::new(TimerWheel::default());
+}
+
+/// Handle to a scheduled timer.
+///
+/// Dropping the handle does not cancel the timer.
+#[derive(Clone, Debug)]
+pub struct TimerHandle {
+    cancelled: Rc<Cell<bool>>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer and drops its callback, or, if the callback is running, drops it as soon as it returns.
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+        // Dropped outside the borrow, the callback's captures may schedule or cancel timers themselves.
+        let removed = TIMERS.try_with(|timers| {
+            let mut timers = timers.borrow_mut();
+            let key = timers
+                .timers
+                .iter()
+                .find(|(_, timer)| Rc::ptr_eq(&timer.cancelled, &self.cancelled))
+                .map(|(key, _)| *key);
+            key.and_then(|key| timers.timers.remove(&key))
+        });
+        drop(removed);
+    }
+
+    /// Returns `true` if the timer was not cancelled and is still going to fire.
+    pub fn is_active(&self) -> bool {
+        !self.cancelled.get()
+    }
+}
+
+fn schedule(delay: Duration, interval: Option<Duration>, callback: Callback) -> TimerHandle {
+    let cancelled = Rc::new(Cell::new(false));
+    TIMERS.with(|timers| {
+        let mut timers = timers.borrow_mut();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.timers.insert(
+            (Instant::now() + delay, id),
+            Timer {
+                interval,
+                cancelled: cancelled.clone(),
+                callback,
+            },
+        );
+    });
+    TimerHandle { cancelled }
+}
+
+/// Schedules `callback` to be called once after `delay`.
+pub fn after<FUNC>(delay: Duration, callback: FUNC) -> TimerHandle
+where
+    FUNC: 'static + FnOnce(lua_State) -> TimerResult,
+{
+    let mut callback = Some(callback);
+    schedule(
+        delay,
+        None,
+        Box::new(move |state| match callback.take() {
+            Some(callback) => callback(state),
+            None => Ok(()),
+        }),
+    )
+}
+
+/// Schedules `callback` to be called every `interval` until it is cancelled.
+///
+/// If the timer falls behind by more than one interval the missed calls are skipped.
+pub fn every<FUNC>(interval: Duration, callback: FUNC) -> TimerHandle
+where
+    FUNC: 'static + FnMut(lua_State) -> TimerResult,
+{
+    schedule(interval, Some(interval), Box::new(callback))
+}
+
+/// Cancels every timer scheduled on the current thread.
+pub fn clear() {
+    let timers = TIMERS.with(|timers| std::mem::take(&mut timers.borrow_mut().timers));
+    for timer in timers.values() {
+        timer.cancelled.set(true);
+    }
+}
+
+/// Fires every timer whose deadline has passed.
+///
+/// Timers scheduled or rescheduled by the callbacks wait for the next call, so an [`every`] timer fires at most once per pump, even with a zero interval.
+///
+/// Each callback runs in protected mode. Errors (both `Err` results and Lua errors) are reported with a traceback through `ErrorNoHalt`, or `print` if it does not exist.
+///
+/// # Safety
+/// `state` must be a valid Lua state owned by the current thread, the thread the timers were scheduled on.
+pub unsafe fn pump(state: lua_State) {
+    check_thread(state);
+    let now = Instant::now();
+    // Timers scheduled from now on get newer ids and wait for the next pump.
+    let first_new_id = TIMERS.with(|timers| timers.borrow().next_id);
+    loop {
+        let due = TIMERS.with(|timers| {
+            let mut timers = timers.borrow_mut();
+            match timers.timers.first_key_value() {
+                Some((&(deadline, id), _)) if deadline <= now && id < first_new_id => {
+                    timers.timers.pop_first()
+                }
+                _ => None,
+            }
+        });
+        let Some(((deadline, _), mut timer)) = due else {
+            break;
+        };
+        if timer.cancelled.get() {
+            continue;
+        }
+        fire(state, &mut timer.callback);
+        match timer.interval {
+            Some(interval) if !timer.cancelled.get() => {
+                let mut next = deadline + interval;
+                if next <= now {
+                    next = now + interval;
+                }
+                TIMERS.with(|timers| {
+                    let mut timers = timers.borrow_mut();
+                    let id = timers.next_id;
+                    timers.next_id += 1;
+                    timers.timers.insert((next, id), timer)
+                });
+            }
+            _ => timer.cancelled.set(true),
+        }
+    }
+}
+
+/// Pushes a Lua function that calls [`pump`] onto the stack.
+///
+/// # Safety
+/// `state` must be a valid Lua state with a free stack slot. The function must only be called on the thread the timers are scheduled on.
+pub unsafe fn pushpump(state: lua_State) {
+    unsafe extern "C" fn pump_callback(state: lua_State) -> i32 {
+        pump(state);
+        0
+    }
+    pushcclosure(state, pump_callback, 0);
+}
+
+unsafe fn fire(state: lua_State, callback: &mut Callback) {
+    unsafe extern "C-unwind" fn call_callback(state: lua_State) -> i32 {
+        let callback = &mut *touserdata(state, upvalueindex!(1)).cast::<Callback>();
+        if let Err(err) = callback(state) {
+            raise(state, err);
+        }
+        0
+    }
+    let top = gettop(state);
+    pushcclosure(state, traceback, 0);
+    pushlightuserdata(state, callback as *mut Callback as *const c_void);
+    pushcclosure(state, cfunction(call_callback), 1);
+    if !matches!(pcall(state, 0, 0, top + 1), Status::Ok) {
+        report_error(state);
+    }
+    settop(state, top);
+}
+
+/// Reports the error message on the top of the stack and pops it.
+pub(crate) unsafe fn report_error(state: lua_State) {
+    let mut len = 0;
+    let message = tolstring(state, -1, &mut len);
+    let mut message = if message.is_null() {
+        b"(error object is not a string)".to_vec()
+    } else {
+        std::slice::from_raw_parts(message, len).to_vec()
+    };
+    pop!(state, 1);
+    getfield(state, GLOBALSINDEX, cstr!("ErrorNoHalt"));
+    if get_type(state, -1) == TFUNCTION {
+        message.push(b'\n');
+    } else {
+        pop!(state, 1);
+        getfield(state, GLOBALSINDEX, cstr!("print"));
+    }
+    pushlstring(state, message.as_ptr(), message.len());
+    if !matches!(pcall(state, 1, 0, 0), Status::Ok) {
+        pop!(state, 1);
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Mutex;
+
+use crate::{
+    absindex, checkstack, createtable, cstr, dump, exec::load_buffer, get_type, getfield,
+    getmetatable, gettop, getupvalue, iscfunction, lua_State, next, pop, pushboolean,
+    pushlightuserdata, pushlstring, pushnil, pushnumber, pushvalue, rawequal, rawget, rawset,
+    remove, setfenv, settop, toboolean, tolstring, tonumber, topointer, touserdata, typename,
+    LError, LoadMode, REGISTRYINDEX, TBOOLEAN, TFUNCTION, TLIGHTUSERDATA, TNIL, TNUMBER, TSTRING,
+    TTABLE, TUSERDATA,
+};
+
+/// Copies a userdata for [`transfer`].
+///
+/// Called with the userdata at `index` in `src`; must push exactly one value onto `dst`.
+pub type CloneHook =
+    unsafe fn(src: lua_State, index: i32, dst: lua_State) -> std::result::Result<(), LError>;
+
+/// Registered hooks, by metatable name (with a trailing NUL).
+static CLONE_HOOKS: Mutex<Vec<(String, CloneHook)>> = Mutex::new(Vec::new());
+
+/// Lets [`transfer`] copy userdata whose metatable is the one registered as `type_name` with [`Lnewmetatable`](crate::Lnewmetatable).
+///
+/// Registering the same name again replaces the hook.
+pub fn register_clone_hook(type_name: &str, hook: CloneHook) {
+    let name = format!("{}\0", type_name);
+    let mut hooks = CLONE_HOOKS.lock().unwrap_or_else(|err| err.into_inner());
+    match hooks.iter_mut().find(|(registered, _)| *registered == name) {
+        Some(entry) => entry.1 = hook,
+        None => hooks.push((name, hook)),
+    }
+}
+
+/// Copies the value at `src_index` in `src` onto the stack of `dst`, another, independent state.
+///
+/// Strings, numbers, booleans and tables are copied deeply; a table reached twice is copied once, so shared tables and cycles are kept.
+/// Metatables of tables are not copied, they usually belong to the source state.
+/// Lua functions without upvalues are copied as bytecode only if `env` is given: the index of a table in `dst` that becomes their environment, so functions coming from a sandbox don't get the globals of `dst`.
+/// Userdata is copied by its [`CloneHook`].
+///
+/// Fails with [`LError::TypeError`] for C functions, functions with upvalues, Lua functions without `env`, threads and userdata without a hook, and leaves both stacks as they were.
+///
+/// # Safety
+/// `src` and `dst` must be valid states that don't share a registry, and `src_index` must be valid in `src`.
+pub unsafe fn transfer(
+    src: lua_State,
+    src_index: i32,
+    dst: lua_State,
+    env: Option<i32>,
+) -> std::result::Result<(), LError> {
+    let src_index = absindex(src, src_index);
+    let env = env.map(|env| absindex(dst, env));
+    if let Some(env) = env {
+        if get_type(dst, env) != TTABLE {
+            return Err(LError::TypeError(format!(
+                "environment must be a table, got {}",
+                type_name(dst, env)
+            )));
+        }
+    }
+    let src_top = gettop(src);
+    if !checkstack(dst, 2) {
+        return Err(stack_overflow());
+    }
+    createtable(dst, 0, 0);
+    let copies = gettop(dst);
+    match copy(src, src_index, dst, copies, env) {
+        Ok(()) => {
+            remove(dst, copies);
+            Ok(())
+        }
+        Err(err) => {
+            settop(src, src_top);
+            settop(dst, copies - 1);
+            Err(err)
+        }
+    }
+}
+
+fn stack_overflow() -> LError {
+    LError::RuntimeMessage(String::from("stack overflow (value nested too deeply)"))
+}
+
+unsafe fn type_name(state: lua_State, index: i32) -> String {
+    std::ffi::CStr::from_ptr(typename(state, get_type(state, index)).cast())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Pushes a copy of the value at `index` (absolute) onto `dst`. `copies` maps source pointers to their copies in `dst`.
+unsafe fn copy(
+    src: lua_State,
+    index: i32,
+    dst: lua_State,
+    copies: i32,
+    env: Option<i32>,
+) -> std::result::Result<(), LError> {
+    match get_type(src, index) {
+        TNIL => pushnil(dst),
+        TBOOLEAN => pushboolean(dst, toboolean(src, index) as i32),
+        TNUMBER => pushnumber(dst, tonumber(src, index)),
+        TSTRING => {
+            let mut len = 0;
+            let ptr = tolstring(src, index, &mut len);
+            pushlstring(dst, ptr, len);
+        }
+        TLIGHTUSERDATA => pushlightuserdata(dst, touserdata(src, index)),
+        kind @ (TTABLE | TFUNCTION | TUSERDATA) => {
+            if !checkstack(src, 3) || !checkstack(dst, 4) {
+                return Err(stack_overflow());
+            }
+            let pointer = topointer(src, index);
+            pushlightuserdata(dst, pointer);
+            rawget(dst, copies);
+            if get_type(dst, -1) != TNIL {
+                return Ok(());
+            }
+            pop!(dst, 1);
+            match kind {
+                // Registers itself before copying the contents, so cycles find it.
+                TTABLE => return copy_table(src, index, dst, copies, env),
+                TFUNCTION => copy_function(src, index, dst, env)?,
+                _ => copy_userdata(src, index, dst)?,
+            }
+            pushlightuserdata(dst, pointer);
+            pushvalue(dst, -2);
+            rawset(dst, copies);
+        }
+        _ => {
+            return Err(LError::TypeError(format!(
+                "cannot transfer a {}",
+                type_name(src, index)
+            )))
+        }
+    }
+    Ok(())
+}
+
+unsafe fn copy_table(
+    src: lua_State,
+    index: i32,
+    dst: lua_State,
+    copies: i32,
+    env: Option<i32>,
+) -> std::result::Result<(), LError> {
+    createtable(dst, 0, 0);
+    pushlightuserdata(dst, topointer(src, index));
+    pushvalue(dst, -2);
+    rawset(dst, copies);
+    pushnil(src);
+    while next(src, index) != 0 {
+        let top = gettop(src);
+        copy(src, top - 1, dst, copies, env)?;
+        copy(src, top, dst, copies, env)?;
+        rawset(dst, -3);
+        pop!(src, 1);
+    }
+    Ok(())
+}
+
+unsafe fn copy_function(
+    src: lua_State,
+    index: i32,
+    dst: lua_State,
+    env: Option<i32>,
+) -> std::result::Result<(), LError> {
+    if iscfunction(src, index) {
+        return Err(LError::TypeError(String::from(
+            "cannot transfer a C function",
+        )));
+    }
+    let Some(env) = env else {
+        return Err(LError::TypeError(String::from(
+            "cannot transfer a function without an environment for it",
+        )));
+    };
+    if !getupvalue(src, index, 1).is_null() {
+        pop!(src, 1);
+        return Err(LError::TypeError(String::from(
+            "cannot transfer a function with upvalues",
+        )));
+    }
+    let mut bytecode = Vec::new();
+    pushvalue(src, index);
+    let dumped = dump(src, &mut bytecode);
+    pop!(src, 1);
+    dumped?;
+    load_buffer(dst, &bytecode, cstr!("=transfer"), LoadMode::Binary)?;
+    pushvalue(dst, env);
+    setfenv(dst, -2);
+    Ok(())
+}
+
+unsafe fn copy_userdata(
+    src: lua_State,
+    index: i32,
+    dst: lua_State,
+) -> std::result::Result<(), LError> {
+    let hooks = CLONE_HOOKS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    let mut hook = None;
+    if getmetatable(src, index) != 0 {
+        for (name, registered) in hooks {
+            getfield(src, REGISTRYINDEX, name.as_ptr());
+            let found = rawequal(src, -1, -2);
+            pop!(src, 1);
+            if found {
+                hook = Some(registered);
+                break;
+            }
+        }
+        pop!(src, 1);
+    }
+    let Some(hook) = hook else {
+        return Err(LError::TypeError(String::from(
+            "cannot transfer a userdata without a clone hook",
+        )));
+    };
+    let top = gettop(dst);
+    hook(src, index, dst)?;
+    if gettop(dst) != top + 1 {
+        return Err(LError::RuntimeMessage(format!(
+            "clone hook pushed {} values instead of 1",
+            gettop(dst) - top
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,657 @@
+//! Rust values as Lua userdata, with operators mapped to metamethods.
+//!
+//! [`Metatable`] builds the metatable of a [`UserData`] type from its trait implementations: `Add`, `Sub`, `Mul`, `Div` and `Neg` become `__add`, `__sub`, `__mul`, `__div` and `__unm`,
+//! `PartialEq` becomes `__eq`, `PartialOrd` becomes `__lt` and `__le`, `Display` becomes `__tostring` and [`Get`] becomes `__index`.
+//! Binary operators are registered per operand pair, so mixed operations like `2 * v` and `v * 2` each get their own implementation and the metamethod picks the one matching both sides.
+//! ```no_run
+//! # use lua_shared::{self as lua, cstr, userdata::{self, Metatable, UserData}};
+//! # use std::ops::{Add, Mul};
+//! #[derive(Clone, Copy, PartialEq)]
+//! struct Vector(f64, f64);
+//!
+//! impl UserData for Vector {
+//!     const NAME: &'static str = "Vector";
+//! }
+//! impl Add for Vector {
+//!     type Output = Vector;
+//!     fn add(self, other: Vector) -> Vector {
+//!         Vector(self.0 + other.0, self.1 + other.1)
+//!     }
+//! }
+//! impl Mul<f64> for Vector {
+//!     type Output = Vector;
+//!     fn mul(self, scale: f64) -> Vector {
+//!         Vector(self.0 * scale, self.1 * scale)
+//!     }
+//! }
+//! impl Mul<Vector> for f64 {
+//!     type Output = Vector;
+//!     fn mul(self, vector: Vector) -> Vector {
+//!         vector * self
+//!     }
+//! }
+//!
+//! # unsafe fn open(state: lua::lua_State) {
+//! Metatable::<Vector>::new()
+//!     .add::<Vector, Vector>()
+//!     .mul::<Vector, f64>()
+//!     .mul::<f64, Vector>()
+//!     .eq()
+//!     .register(state);
+//! lua::pushfunction(state, |state| {
+//!     userdata::push(state, Vector(lua::Lchecknumber(state, 1), lua::Lchecknumber(state, 2)));
+//!     Ok(1)
+//! });
+//! lua::setglobal!(state, cstr!("Vector"));
+//! // Lua: print(2 * (Vector(1, 2) + Vector(3, 4)) == Vector(8, 12)) --> true
+//! # }
+//! ```
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    fmt::Display,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use crate::{
+    convert::Integer, createtable, cstr, get_type, getfield, getmetatable, gettop, lua_CFunction,
+    lua_State, newuserdata, pcall_callback, pop, pop_message, pushboolean, pushcclosure,
+    pushfunction, pushlightuserdata, pushlstring, pushnil, pushvalue, rawequal, rawget, rawset,
+    setfield, setmetatable, settop, tolstring, tonumber, touserdata, type_key, typename, IntoLua,
+    Lnewmetatable, Result, REGISTRYINDEX, TNIL, TNUMBER, TSTRING, TTABLE, TUSERDATA,
+};
+
+/// Registry field holding the metatables of [`UserData`] types, by type key.
+const REGISTRY_FIELD: *const u8 = cstr!("lua_shared.userdata");
+
+/// A Rust type that can live in Lua as a userdata.
+pub trait UserData: 'static + Sized {
+    /// Name of the type, used for `__name` and [`Lnewmetatable`].
+    const NAME: &'static str;
+}
+
+impl<T> IntoLua for T
+where
+    T: UserData,
+{
+    unsafe fn into_lua(self, state: lua_State) {
+        push(state, self);
+    }
+}
+
+/// Indexing that can miss, for [`Metatable::index`]. Unlike [`Index`](std::ops::Index), a missing key is not a panic.
+pub trait Get<KEY> {
+    type Output: IntoLua;
+
+    /// Returns the value stored under `key`, or `None` if there is none.
+    fn get(&self, key: KEY) -> Option<Self::Output>;
+}
+
+/// Types that can be read as an operand of a metamethod.
+pub trait Operand: Sized {
+    /// Reads the value at `index`, or returns `None` if it is of another type.
+    ///
+    /// # Safety
+    /// `index` must be valid in `state`, with room for two more stack slots.
+    unsafe fn from_operand(state: lua_State, index: i32) -> Option<Self>;
+}
+
+impl<T> Operand for T
+where
+    T: UserData + Clone,
+{
+    unsafe fn from_operand(state: lua_State, index: i32) -> Option<Self> {
+        check::<T>(state, index).cloned()
+    }
+}
+
+macro_rules! impl_operand_float {
+    ($($ty:ty),*) => {
+        $(
+            impl Operand for $ty {
+                unsafe fn from_operand(state: lua_State, index: i32) -> Option<Self> {
+                    match get_type(state, index) {
+                        TNUMBER => Some(tonumber(state, index) as $ty),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_operand_float!(f64, f32);
+
+macro_rules! impl_operand_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl Operand for $ty {
+                /// Numbers that are not integral or out of range are of another type too.
+                unsafe fn from_operand(state: lua_State, index: i32) -> Option<Self> {
+                    match get_type(state, index) {
+                        TNUMBER => <$ty>::from_number(tonumber(state, index)),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_operand_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl Operand for String {
+    unsafe fn from_operand(state: lua_State, index: i32) -> Option<Self> {
+        if get_type(state, index) != TSTRING {
+            return None;
+        }
+        let mut len = 0;
+        let ptr = tolstring(state, index, &mut len);
+        String::from_utf8(std::slice::from_raw_parts(ptr, len).to_vec()).ok()
+    }
+}
+
+/// Pushes the metatable of `T`, or `nil` if it isn't registered.
+unsafe fn push_metatable<T>(state: lua_State)
+where
+    T: UserData,
+{
+    getfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+    if get_type(state, -1) != TTABLE {
+        pop!(state, 1);
+        pushnil(state);
+        return;
+    }
+    pushlightuserdata(state, type_key::<T>());
+    rawget(state, -2);
+    crate::remove(state, -2);
+}
+
+/// What the userdata of a `T` holds.
+struct Slot<T> {
+    /// Set while a method runs, so a method reaching the same value again doesn't get a second `&mut T`.
+    borrowed: Cell<bool>,
+    value: UnsafeCell<T>,
+}
+
+/// Panics if `T` needs more than 8-byte alignment, which is all Lua guarantees for userdata.
+fn assert_alignment<T>()
+where
+    T: UserData,
+{
+    assert!(
+        std::mem::align_of::<Slot<T>>() <= 8,
+        "{} needs more alignment than Lua userdata has",
+        T::NAME
+    );
+}
+
+/// Pushes `value` as a userdata with the metatable of `T`, registering a plain one (with just `__gc` and `__name`) if there is none yet.
+///
+/// Panics if `T` needs more than 8-byte alignment.
+///
+/// # Safety
+/// `state` must be a valid Lua state with room for three more stack slots.
+pub unsafe fn push<T>(state: lua_State, value: T)
+where
+    T: UserData,
+{
+    assert_alignment::<T>();
+    newuserdata(state, std::mem::size_of::<Slot<T>>())
+        .cast::<Slot<T>>()
+        .write(Slot {
+            borrowed: Cell::new(false),
+            value: UnsafeCell::new(value),
+        });
+    push_metatable::<T>(state);
+    if get_type(state, -1) == TNIL {
+        pop!(state, 1);
+        Metatable::<T>::new().register(state);
+        push_metatable::<T>(state);
+    }
+    setmetatable(state, -2);
+}
+
+/// Returns the `T` at `index`, or `None` if it is not a userdata with the metatable of `T`, or if a method of the value is running.
+///
+/// The reference points into the userdata: it must not outlive it, and must not be held while Lua code that can reach the same value runs.
+///
+/// # Safety
+/// Besides the above, `index` must be valid in `state`, with room for two more stack slots.
+pub unsafe fn check<'a, T>(state: lua_State, index: i32) -> Option<&'a mut T>
+where
+    T: UserData,
+{
+    match slot::<T>(state, index) {
+        Some(slot) if !slot.borrowed.get() => Some(&mut *slot.value.get()),
+        _ => None,
+    }
+}
+
+/// Returns the slot at `index` if it is a userdata with the metatable of `T`.
+unsafe fn slot<'a, T>(state: lua_State, index: i32) -> Option<&'a Slot<T>>
+where
+    T: UserData,
+{
+    if get_type(state, index) != TUSERDATA || getmetatable(state, index) == 0 {
+        return None;
+    }
+    push_metatable::<T>(state);
+    let matches = rawequal(state, -1, -2);
+    pop!(state, 2);
+    match matches {
+        true => touserdata(state, index).cast::<Slot<T>>().as_ref(),
+        false => None,
+    }
+}
+
+/// Runs `method` on the value at index 1, refusing values that are already in use by a method further up the call stack.
+unsafe fn call_method<T>(state: lua_State, name: &str, method: &mut Method<T>) -> Result
+where
+    T: UserData,
+{
+    match slot::<T>(state, 1) {
+        Some(slot) if slot.borrowed.get() => Err(format!(
+            "cannot call '{}', the {} is already in use by another method",
+            name,
+            T::NAME
+        )
+        .into()),
+        Some(slot) => {
+            slot.borrowed.set(true);
+            // Lua errors raised by the method are caught, so the flag is cleared before they are passed on.
+            let result = pcall_callback(state, &mut |state| method(state, &mut *slot.value.get()));
+            slot.borrowed.set(false);
+            match result {
+                Some(nrets) => Ok(nrets),
+                None => Err(pop_message(state).into()),
+            }
+        }
+        None => Err(format!("bad self to '{}' ({} expected)", name, T::NAME).into()),
+    }
+}
+
+/// Reads the operands of a binary metamethod and pushes the result. Returns `false` if they are of other types.
+type Handler = unsafe fn(state: lua_State) -> bool;
+
+macro_rules! binary_handler {
+    ($name:ident, $trait:ident, $method:ident) => {
+        unsafe fn $name<LHS, RHS>(state: lua_State) -> bool
+        where
+            LHS: Operand + $trait<RHS>,
+            RHS: Operand,
+            LHS::Output: IntoLua,
+        {
+            match (LHS::from_operand(state, 1), RHS::from_operand(state, 2)) {
+                (Some(lhs), Some(rhs)) => {
+                    lhs.$method(rhs).into_lua(state);
+                    true
+                }
+                _ => false,
+            }
+        }
+    };
+}
+
+binary_handler!(add_handler, Add, add);
+binary_handler!(sub_handler, Sub, sub);
+binary_handler!(mul_handler, Mul, mul);
+binary_handler!(div_handler, Div, div);
+
+unsafe fn index_handler<T, KEY>(state: lua_State) -> bool
+where
+    T: UserData + Get<KEY>,
+    KEY: Operand,
+{
+    let value = match (check::<T>(state, 1), KEY::from_operand(state, 2)) {
+        (Some(this), Some(key)) => this.get(key),
+        _ => None,
+    };
+    match value {
+        Some(value) => {
+            value.into_lua(state);
+            true
+        }
+        None => false,
+    }
+}
+
+type Method<T> = Box<dyn FnMut(lua_State, &mut T) -> Result>;
+
+/// Builder for the metatable of a [`UserData`] type.
+pub struct Metatable<T> {
+    operators: Vec<(&'static str, Handler)>,
+    index: Vec<Handler>,
+    functions: Vec<(&'static str, lua_CFunction)>,
+    methods: Vec<(String, Method<T>)>,
+    call: Option<Method<T>>,
+}
+
+impl<T> Default for Metatable<T>
+where
+    T: UserData,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Metatable<T>
+where
+    T: UserData,
+{
+    pub fn new() -> Self {
+        Self {
+            operators: Vec::new(),
+            index: Vec::new(),
+            functions: Vec::new(),
+            methods: Vec::new(),
+            call: None,
+        }
+    }
+
+    fn operator(mut self, metamethod: &'static str, handler: Handler) -> Self {
+        self.operators.push((metamethod, handler));
+        self
+    }
+
+    fn function(mut self, metamethod: &'static str, function: lua_CFunction) -> Self {
+        self.functions.retain(|(other, _)| *other != metamethod);
+        self.functions.push((metamethod, function));
+        self
+    }
+
+    /// Maps `LHS + RHS` to `__add`. One of the operands should be `T`.
+    pub fn add<LHS, RHS>(self) -> Self
+    where
+        LHS: Operand + Add<RHS>,
+        RHS: Operand,
+        LHS::Output: IntoLua,
+    {
+        self.operator("__add", add_handler::<LHS, RHS>)
+    }
+
+    /// Maps `LHS - RHS` to `__sub`. One of the operands should be `T`.
+    pub fn sub<LHS, RHS>(self) -> Self
+    where
+        LHS: Operand + Sub<RHS>,
+        RHS: Operand,
+        LHS::Output: IntoLua,
+    {
+        self.operator("__sub", sub_handler::<LHS, RHS>)
+    }
+
+    /// Maps `LHS * RHS` to `__mul`. One of the operands should be `T`.
+    pub fn mul<LHS, RHS>(self) -> Self
+    where
+        LHS: Operand + Mul<RHS>,
+        RHS: Operand,
+        LHS::Output: IntoLua,
+    {
+        self.operator("__mul", mul_handler::<LHS, RHS>)
+    }
+
+    /// Maps `LHS / RHS` to `__div`. One of the operands should be `T`.
+    pub fn div<LHS, RHS>(self) -> Self
+    where
+        LHS: Operand + Div<RHS>,
+        RHS: Operand,
+        LHS::Output: IntoLua,
+    {
+        self.operator("__div", div_handler::<LHS, RHS>)
+    }
+
+    /// Maps `-value` to `__unm`, through [`Neg`].
+    pub fn unm(self) -> Self
+    where
+        T: Clone + Neg,
+        T::Output: IntoLua,
+    {
+        unsafe extern "C" fn unm<T>(state: lua_State) -> i32
+        where
+            T: UserData + Clone + Neg,
+            T::Output: IntoLua,
+        {
+            match check::<T>(state, 1) {
+                Some(this) => {
+                    (-this.clone()).into_lua(state);
+                    1
+                }
+                None => 0,
+            }
+        }
+        self.function("__unm", unm::<T>)
+    }
+
+    /// Maps `==` to `__eq`. Lua only calls it when both operands are userdata of this type.
+    pub fn eq(self) -> Self
+    where
+        T: PartialEq,
+    {
+        unsafe extern "C" fn eq<T>(state: lua_State) -> i32
+        where
+            T: UserData + PartialEq,
+        {
+            let equal = match (check::<T>(state, 1), check::<T>(state, 2)) {
+                (Some(lhs), Some(rhs)) => lhs == rhs,
+                _ => false,
+            };
+            equal.into_lua(state);
+            1
+        }
+        self.function("__eq", eq::<T>)
+    }
+
+    /// Maps `<` and `<=` to `__lt` and `__le`. Lua only calls them when both operands are userdata of this type.
+    pub fn ord(self) -> Self
+    where
+        T: PartialOrd,
+    {
+        unsafe extern "C" fn lt<T>(state: lua_State) -> i32
+        where
+            T: UserData + PartialOrd,
+        {
+            let less = match (check::<T>(state, 1), check::<T>(state, 2)) {
+                (Some(lhs), Some(rhs)) => lhs < rhs,
+                _ => false,
+            };
+            less.into_lua(state);
+            1
+        }
+        unsafe extern "C" fn le<T>(state: lua_State) -> i32
+        where
+            T: UserData + PartialOrd,
+        {
+            let less_equal = match (check::<T>(state, 1), check::<T>(state, 2)) {
+                (Some(lhs), Some(rhs)) => lhs <= rhs,
+                _ => false,
+            };
+            less_equal.into_lua(state);
+            1
+        }
+        self.function("__lt", lt::<T>).function("__le", le::<T>)
+    }
+
+    /// Maps `tostring(value)` to [`Display`].
+    pub fn display(self) -> Self
+    where
+        T: Display,
+    {
+        unsafe extern "C" fn tostring<T>(state: lua_State) -> i32
+        where
+            T: UserData + Display,
+        {
+            match check::<T>(state, 1) {
+                Some(this) => this.to_string().into_lua(state),
+                None => T::NAME.into_lua(state),
+            }
+            1
+        }
+        self.function("__tostring", tostring::<T>)
+    }
+
+    /// Maps `value[key]` to [`Get`] for keys of type `KEY`. Keys are passed as is, so integer indices start at 1 only if `T` does so.
+    ///
+    /// Methods take precedence; keys of other types, and keys `T` returns `None` for, give `nil`.
+    pub fn index<KEY>(mut self) -> Self
+    where
+        T: Get<KEY>,
+        KEY: Operand,
+    {
+        self.index.push(index_handler::<T, KEY>);
+        self
+    }
+
+    /// Adds a method, called as `value:name(...)`. The value is at index 1 and the arguments start at index 2.
+    pub fn method<FUNC>(mut self, name: &str, method: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State, &mut T) -> Result,
+    {
+        self.methods.retain(|(other, _)| other != name);
+        self.methods.push((name.to_string(), Box::new(method)));
+        self
+    }
+
+    /// Makes the value callable: `value(...)` runs `call` with the value at index 1 and the arguments from index 2.
+    ///
+    /// Stands in for `Fn`, which can't be implemented for user types.
+    pub fn call<FUNC>(mut self, call: FUNC) -> Self
+    where
+        FUNC: 'static + FnMut(lua_State, &mut T) -> Result,
+    {
+        self.call = Some(Box::new(call));
+        self
+    }
+
+    /// Creates (or updates) the metatable of `T` in `state`. It is also registered under [`UserData::NAME`] like with [`Lnewmetatable`].
+    ///
+    /// Panics if `T` needs more than 8-byte alignment, which is all Lua guarantees for userdata.
+    ///
+    /// # Safety
+    /// `state` must be a valid Lua state owned by the current thread.
+    pub unsafe fn register(self, state: lua_State) {
+        unsafe extern "C" fn gc<T>(state: lua_State) -> i32
+        where
+            T: UserData,
+        {
+            // `__gc` can also be reached through the debug library, with anything as its argument.
+            match slot::<T>(state, 1) {
+                Some(slot) if !slot.borrowed.get() => {
+                    // Without its metatable the userdata is neither dropped again nor accepted by `check`.
+                    pushnil(state);
+                    setmetatable(state, 1);
+                    (slot as *const Slot<T>).cast_mut().drop_in_place();
+                }
+                _ => {}
+            }
+            0
+        }
+
+        assert_alignment::<T>();
+
+        let name = format!("{}\0", T::NAME);
+        let base = gettop(state);
+        getfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+        if get_type(state, -1) != TTABLE {
+            pop!(state, 1);
+            createtable(state, 0, 1);
+            pushvalue(state, -1);
+            setfield(state, REGISTRYINDEX, REGISTRY_FIELD);
+        }
+        Lnewmetatable(state, name.as_ptr());
+        let metatable = gettop(state);
+        pushlightuserdata(state, type_key::<T>());
+        pushvalue(state, metatable);
+        rawset(state, base + 1);
+
+        T::NAME.into_lua(state);
+        setfield(state, metatable, cstr!("__name"));
+        pushcclosure(state, gc::<T>, 0);
+        setfield(state, metatable, cstr!("__gc"));
+        pushboolean(state, 0);
+        setfield(state, metatable, cstr!("__metatable"));
+
+        let mut metamethods: Vec<(&'static str, Vec<Handler>)> = Vec::new();
+        for (metamethod, handler) in self.operators {
+            match metamethods
+                .iter_mut()
+                .find(|(other, _)| *other == metamethod)
+            {
+                Some((_, handlers)) => handlers.push(handler),
+                None => metamethods.push((metamethod, vec![handler])),
+            }
+        }
+        for (metamethod, handlers) in metamethods {
+            pushfunction(state, move |state| {
+                // Both operands stay at 1 and 2; a handler that doesn't match pushes nothing.
+                settop(state, 2);
+                for handler in &handlers {
+                    if handler(state) {
+                        return Ok(1);
+                    }
+                }
+                Err(format!(
+                    "attempt to perform arithmetic ({}) on {} and {}",
+                    metamethod,
+                    type_name(state, 1),
+                    type_name(state, 2)
+                )
+                .into())
+            });
+            pushlstring(state, metamethod.as_ptr(), metamethod.len());
+            crate::insert(state, -2);
+            rawset(state, metatable);
+        }
+        for (metamethod, function) in self.functions {
+            pushlstring(state, metamethod.as_ptr(), metamethod.len());
+            pushcclosure(state, function, 0);
+            rawset(state, metatable);
+        }
+        if let Some(mut call) = self.call {
+            pushfunction(state, move |state| call_method(state, "__call", &mut call));
+            setfield(state, metatable, cstr!("__call"));
+        }
+
+        createtable(state, 0, self.methods.len() as i32);
+        let methods = gettop(state);
+        for (name, mut method) in self.methods {
+            pushlstring(state, name.as_ptr(), name.len());
+            pushfunction(state, move |state| call_method(state, &name, &mut method));
+            rawset(state, methods);
+        }
+        if self.index.is_empty() {
+            setfield(state, metatable, cstr!("__index"));
+        } else {
+            let handlers = self.index;
+            // Methods are looked up first, from the metatable.
+            setfield(state, metatable, cstr!("__methods"));
+            pushfunction(state, move |state| {
+                settop(state, 2);
+                if getmetatable(state, 1) != 0 {
+                    getfield(state, -1, cstr!("__methods"));
+                    pushvalue(state, 2);
+                    rawget(state, -2);
+                    if get_type(state, -1) != TNIL {
+                        return Ok(1);
+                    }
+                }
+                settop(state, 2);
+                for handler in &handlers {
+                    if handler(state) {
+                        return Ok(1);
+                    }
+                }
+                pushnil(state);
+                Ok(1)
+            });
+            setfield(state, metatable, cstr!("__index"));
+        }
+        settop(state, base);
+    }
+}
+
+unsafe fn type_name(state: lua_State, index: i32) -> String {
+    std::ffi::CStr::from_ptr(typename(state, get_type(state, index)).cast())
+        .to_string_lossy()
+        .into_owned()
+}
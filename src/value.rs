@@ -0,0 +1,430 @@
+use std::{cell::RefCell, collections::HashMap, ffi::c_void, fmt, rc::Rc};
+
+use crate::{
+    absindex, checkstack, convert::FromLua, createtable, cstr, get_type, getmetatable, gettop,
+    lua_State, next, pop, pushboolean, pushlightuserdata, pushlstring, pushnil, pushnumber,
+    pushvalue, rawget, rawgeti, rawset, remove, setmetatable, settop, toboolean, tolstring,
+    tonumber, topointer, touserdata, LError, Lcheckstack, Lerror, Lref, Lunref, REGISTRYINDEX,
+    TBOOLEAN, TFUNCTION, TLIGHTUSERDATA, TNUMBER, TSTRING, TTABLE, TTHREAD, TUSERDATA,
+};
+
+/// Depth limit used when a [`Value`] is read with [`FromLua`].
+pub const DEFAULT_DEPTH_LIMIT: usize = 64;
+
+/// A Lua value kept alive in the registry with [`Lref`].
+///
+/// The reference is released when dropped, so it must be dropped on the Lua thread and before the state is closed.
+pub struct Reference {
+    state: lua_State,
+    id: i32,
+    pointer: *const c_void,
+}
+
+impl Reference {
+    /// Refers to the value at `index`.
+    ///
+    /// # Safety
+    /// `index` must be valid in `state`, with room for one more stack slot.
+    pub unsafe fn new(state: lua_State, index: i32) -> Self {
+        pushvalue(state, index);
+        let pointer = topointer(state, -1);
+        Self {
+            state,
+            id: Lref(state, REGISTRYINDEX),
+            pointer,
+        }
+    }
+
+    /// The state the reference was created in.
+    pub fn state(&self) -> lua_State {
+        self.state
+    }
+
+    /// The address of the value, as returned by [`topointer`]. Two references to the same value have the same pointer.
+    pub fn pointer(&self) -> *const c_void {
+        self.pointer
+    }
+
+    /// Pushes the value onto the stack of `state`, which must share the registry of the state the reference was created in (it is that state or one of its threads).
+    ///
+    /// # Safety
+    /// The state the reference was created in must still be open, and `state` must have room for one more slot.
+    pub unsafe fn push(&self, state: lua_State) {
+        rawgeti(state, REGISTRYINDEX, self.id);
+    }
+}
+
+impl Clone for Reference {
+    fn clone(&self) -> Self {
+        unsafe {
+            self.push(self.state);
+            Self {
+                state: self.state,
+                id: Lref(self.state, REGISTRYINDEX),
+                pointer: self.pointer,
+            }
+        }
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        unsafe { Lunref(self.state, REGISTRYINDEX, self.id) };
+    }
+}
+
+impl PartialEq for Reference {
+    fn eq(&self, other: &Self) -> bool {
+        self.pointer == other.pointer
+    }
+}
+
+impl fmt::Debug for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:p}", self.pointer)
+    }
+}
+
+/// The contents of a table in a [`Value`].
+#[derive(Clone, Default)]
+pub struct Table {
+    /// Key-value pairs, in the order `next` returned them.
+    pub entries: Vec<(Value, Value)>,
+    /// The metatable, kept by reference so it stays shared with other values of the same class.
+    pub metatable: Option<Reference>,
+    /// Set for tables nested deeper than the depth limit of [`Value::snapshot`]; their entries were not captured.
+    pub truncated: bool,
+}
+
+impl Table {
+    /// Returns the value stored under `key`.
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == key)
+            .map(|(_, value)| value)
+    }
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_table(f, self, &mut Vec::new())
+    }
+}
+
+/// An owned copy of a Lua value that doesn't need the stack.
+///
+/// Tables are copied, with shared tables and cycles kept as shared [`Rc`]s; cyclic snapshots are leaked unless the cycle is broken by hand.
+/// Functions, userdata and threads can't be copied and are kept by [`Reference`].
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Rc<RefCell<Table>>),
+    Function(Reference),
+    UserData(Reference),
+    LightUserData(*mut c_void),
+    Thread(Reference),
+}
+
+impl Value {
+    /// Copies the value at `index`.
+    ///
+    /// `depth_limit` is the number of table levels copied: with `1` the table at `index` is copied but the tables inside it are left [`truncated`](Table::truncated).
+    /// A table reached twice is copied once, so shared tables and cycles come out as the same [`Rc`]; it is copied as deep as its shallowest occurrence allows.
+    ///
+    /// # Safety
+    /// `index` must be valid in `state`. References in the copy must be dropped before the state is closed.
+    pub unsafe fn snapshot(state: lua_State, index: i32, depth_limit: usize) -> Self {
+        snapshot(
+            state,
+            absindex(state, index),
+            depth_limit,
+            &mut HashMap::new(),
+        )
+    }
+
+    /// Pushes the value onto the stack, recreating tables.
+    ///
+    /// Every [`Rc`] becomes one new Lua table, so shared tables and cycles are recreated as well.
+    /// Entries with a `nil` or NaN key are skipped. References must belong to `state` (see [`Reference::push`]).
+    ///
+    /// # Safety
+    /// Raises a Lua error if the tables are nested too deep for the stack, so it must run where Lua errors are caught.
+    pub unsafe fn push(&self, state: lua_State) {
+        Lcheckstack(state, 2, cstr!("too many nested tables"));
+        createtable(state, 0, 0);
+        let created = gettop(state);
+        // Raised only once `push` has returned, so no `RefCell` is left borrowed.
+        if !push(self, state, created) {
+            settop(state, created - 1);
+            Lerror(state, cstr!("too many nested tables"));
+        }
+        remove(state, created);
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+}
+
+impl FromLua for Value {
+    /// Takes a [`Value::snapshot`] with [`DEFAULT_DEPTH_LIMIT`].
+    unsafe fn from_lua(state: lua_State, index: i32) -> std::result::Result<Self, LError> {
+        Ok(Value::snapshot(state, index, DEFAULT_DEPTH_LIMIT))
+    }
+}
+
+impl PartialEq for Value {
+    /// Compares tables by contents, everything else like Lua's raw equality.
+    fn eq(&self, other: &Self) -> bool {
+        equal(self, other, &mut Vec::new())
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_value(f, self, &mut Vec::new())
+    }
+}
+
+/// Tables copied so far, with the depth they were copied to.
+type Seen = HashMap<*const c_void, (Rc<RefCell<Table>>, usize)>;
+
+unsafe fn snapshot(state: lua_State, index: i32, depth: usize, seen: &mut Seen) -> Value {
+    match get_type(state, index) {
+        TBOOLEAN => Value::Boolean(toboolean(state, index)),
+        TNUMBER => Value::Number(tonumber(state, index)),
+        TSTRING => {
+            let mut len = 0;
+            let ptr = tolstring(state, index, &mut len);
+            Value::String(std::slice::from_raw_parts(ptr, len).to_vec())
+        }
+        TTABLE => {
+            let pointer = topointer(state, index);
+            let table = match seen.get(&pointer) {
+                Some((table, copied)) if *copied >= depth => return Value::Table(table.clone()),
+                // Reached again with more levels left than the first time: copy it again, deeper, into the same `Rc`.
+                Some((table, _)) => table.clone(),
+                None => Rc::new(RefCell::new(Table::default())),
+            };
+            seen.insert(pointer, (table.clone(), depth));
+            if !checkstack(state, 3) {
+                table.borrow_mut().truncated = true;
+                return Value::Table(table);
+            }
+            if table.borrow().metatable.is_none() && getmetatable(state, index) != 0 {
+                table.borrow_mut().metatable = Some(Reference::new(state, -1));
+                pop!(state, 1);
+            }
+            table.borrow_mut().entries.clear();
+            if depth == 0 {
+                table.borrow_mut().truncated = true;
+                return Value::Table(table);
+            }
+            table.borrow_mut().truncated = false;
+            pushnil(state);
+            while next(state, index) != 0 {
+                let top = gettop(state);
+                let key = snapshot(state, top - 1, depth - 1, seen);
+                let value = snapshot(state, top, depth - 1, seen);
+                table.borrow_mut().entries.push((key, value));
+                pop!(state, 1);
+            }
+            Value::Table(table)
+        }
+        TFUNCTION => Value::Function(Reference::new(state, index)),
+        TUSERDATA => Value::UserData(Reference::new(state, index)),
+        TTHREAD => Value::Thread(Reference::new(state, index)),
+        TLIGHTUSERDATA => Value::LightUserData(touserdata(state, index)),
+        _ => Value::Nil,
+    }
+}
+
+/// Pushes `value`. `created` is a table mapping every [`Rc`] already pushed to its Lua table.
+///
+/// Returns `false` if the stack can't grow, leaving whatever was pushed so far on it.
+unsafe fn push(value: &Value, state: lua_State, created: i32) -> bool {
+    match value {
+        Value::Nil => pushnil(state),
+        Value::Boolean(boolean) => pushboolean(state, *boolean as i32),
+        Value::Number(number) => pushnumber(state, *number),
+        Value::String(string) => pushlstring(state, string.as_ptr(), string.len()),
+        Value::Table(table) => {
+            let key = Rc::as_ptr(table) as *const c_void;
+            pushlightuserdata(state, key);
+            rawget(state, created);
+            if get_type(state, -1) == TTABLE {
+                return true;
+            }
+            pop!(state, 1);
+            if !checkstack(state, 4) {
+                return false;
+            }
+            let table = table.borrow();
+            createtable(state, 0, table.entries.len() as i32);
+            pushlightuserdata(state, key);
+            pushvalue(state, -2);
+            rawset(state, created);
+            for (key, value) in &table.entries {
+                match key {
+                    Value::Nil => continue,
+                    Value::Number(number) if number.is_nan() => continue,
+                    _ => {}
+                }
+                if !push(key, state, created) || !push(value, state, created) {
+                    return false;
+                }
+                rawset(state, -3);
+            }
+            if let Some(metatable) = &table.metatable {
+                metatable.push(state);
+                setmetatable(state, -2);
+            }
+        }
+        Value::Function(reference) | Value::UserData(reference) | Value::Thread(reference) => {
+            reference.push(state)
+        }
+        Value::LightUserData(pointer) => pushlightuserdata(state, *pointer),
+    }
+    true
+}
+
+type TablePair = (*const RefCell<Table>, *const RefCell<Table>);
+
+fn equal(a: &Value, b: &Value, seen: &mut Vec<TablePair>) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Table(a), Value::Table(b)) => {
+            let pair = (Rc::as_ptr(a), Rc::as_ptr(b));
+            // Tables already being compared are assumed equal, which ends cycles.
+            if Rc::ptr_eq(a, b) || seen.contains(&pair) {
+                return true;
+            }
+            seen.push(pair);
+            let (a, b) = (a.borrow(), b.borrow());
+            if a.truncated != b.truncated
+                || a.metatable != b.metatable
+                || a.entries.len() != b.entries.len()
+            {
+                return false;
+            }
+            for (index, (key, value)) in a.entries.iter().enumerate() {
+                // Copies of the same table usually list their keys in the same order.
+                let other = match b.entries.get(index) {
+                    Some((other_key, other)) if equal(key, other_key, seen) => Some(other),
+                    _ => b
+                        .entries
+                        .iter()
+                        .find(|(other_key, _)| equal(key, other_key, seen))
+                        .map(|(_, other)| other),
+                };
+                match other {
+                    Some(other) if equal(value, other, seen) => {}
+                    _ => return false,
+                }
+            }
+            true
+        }
+        (Value::Function(a), Value::Function(b))
+        | (Value::UserData(a), Value::UserData(b))
+        | (Value::Thread(a), Value::Thread(b)) => a == b,
+        (Value::LightUserData(a), Value::LightUserData(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn format_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    seen: &mut Vec<*const RefCell<Table>>,
+) -> fmt::Result {
+    match value {
+        Value::Nil => write!(f, "nil"),
+        Value::Boolean(boolean) => write!(f, "{}", boolean),
+        Value::Number(number) => write!(f, "{}", number),
+        Value::String(string) => write!(f, "{:?}", String::from_utf8_lossy(string)),
+        Value::Table(table) => {
+            if seen.contains(&Rc::as_ptr(table)) {
+                return write!(f, "<cycle {:p}>", Rc::as_ptr(table));
+            }
+            seen.push(Rc::as_ptr(table));
+            let result = format_table(f, &table.borrow(), seen);
+            seen.pop();
+            result
+        }
+        Value::Function(reference) => write!(f, "function: {:?}", reference),
+        Value::UserData(reference) => write!(f, "userdata: {:?}", reference),
+        Value::LightUserData(pointer) => write!(f, "userdata: {:p}", *pointer),
+        Value::Thread(reference) => write!(f, "thread: {:?}", reference),
+    }
+}
+
+/// Writes `{name = value, [key] = value}` like a Lua constructor.
+fn format_table(
+    f: &mut fmt::Formatter<'_>,
+    table: &Table,
+    seen: &mut Vec<*const RefCell<Table>>,
+) -> fmt::Result {
+    if table.truncated {
+        return write!(f, "{{...}}");
+    }
+    write!(f, "{{")?;
+    for (index, (key, value)) in table.entries.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        match key {
+            Value::String(name) if is_identifier(name) => {
+                write!(f, "{} = ", String::from_utf8_lossy(name))?
+            }
+            key => {
+                write!(f, "[")?;
+                format_value(f, key, seen)?;
+                write!(f, "] = ")?;
+            }
+        }
+        format_value(f, value, seen)?;
+    }
+    write!(f, "}}")
+}
+
+fn is_identifier(name: &[u8]) -> bool {
+    const KEYWORDS: &[&[u8]] = &[
+        b"and",
+        b"break",
+        b"do",
+        b"else",
+        b"elseif",
+        b"end",
+        b"false",
+        b"for",
+        b"function",
+        b"goto",
+        b"if",
+        b"in",
+        b"local",
+        b"nil",
+        b"not",
+        b"or",
+        b"repeat",
+        b"return",
+        b"then",
+        b"true",
+        b"until",
+        b"while",
+    ];
+    match name.first() {
+        Some(first) if first.is_ascii_alphabetic() || *first == b'_' => {
+            name.iter()
+                .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+                && !KEYWORDS.contains(&name)
+        }
+        _ => false,
+    }
+}
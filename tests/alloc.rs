@@ -0,0 +1,58 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{self as lua, testing::lua_test, LError, Lua};
+
+/// The vendored LuaJIT is built with GC64, so custom allocators are supported.
+unsafe fn new_lua() -> Lua {
+    let lua = Lua::with_allocator(None).expect("custom allocators are not supported");
+    lua::Lopenlibs(lua.as_ptr());
+    lua
+}
+
+#[lua_test(no_open)]
+unsafe fn tracks_memory(_state: lua::lua_State) -> Result<(), LError> {
+    let lua = new_lua();
+    let start = lua.memory();
+    assert!(start.live > 0);
+    assert_eq!(start.peak, start.live);
+    assert_eq!(start.limit, None);
+    lua::exec::<()>(lua.as_ptr(), "data = string.rep('x', 1000000)", "test")?;
+    let grown = lua.memory();
+    assert!(grown.live >= start.live + 1000000, "{:?}", grown);
+    lua::exec::<()>(lua.as_ptr(), "data = nil collectgarbage()", "test")?;
+    let collected = lua.memory();
+    assert!(collected.live < grown.live, "{:?}", collected);
+    assert!(collected.peak >= grown.live);
+    lua.reset_peak();
+    assert_eq!(lua.memory().peak, collected.live);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_allocations_past_the_limit(_state: lua::lua_State) -> Result<(), LError> {
+    let lua = new_lua();
+    let limit = lua.memory().live + 256 * 1024;
+    lua.set_limit(Some(limit));
+    assert_eq!(lua.memory().limit, Some(limit));
+    let result = lua::exec::<()>(
+        lua.as_ptr(),
+        "local t = {} for i = 1, 1e7 do t[i] = i end",
+        "test",
+    );
+    assert!(
+        matches!(result, Err(LError::MemoryError(_))),
+        "{:?}",
+        result
+    );
+    assert!(lua.memory().peak <= limit);
+    // The state is still usable once the garbage is gone.
+    lua.set_limit(None);
+    let sum: f64 = lua::exec(lua.as_ptr(), "collectgarbage() return 1 + 2", "test")?;
+    assert_eq!(sum, 3.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn tiny_limits_fail_to_open(_state: lua::lua_State) {
+    assert!(Lua::with_allocator(Some(16)).is_none());
+}
@@ -0,0 +1,131 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::time::Duration;
+
+use lua_shared::{self as lua, cstr, testing::lua_test, Budget, LError};
+
+/// Defines `spin(n)` and pushes it with `n`, with the JIT off so hooks run in loops.
+unsafe fn push_spin(state: lua::lua_State, iterations: f64) -> Result<(), LError> {
+    lua::exec::<()>(
+        state,
+        r#"
+        jit.off()
+        function spin(n)
+            local total = 0
+            for i = 1, n do total = total + i end
+            return total
+        end
+        "#,
+        "test",
+    )?;
+    lua::getglobal!(state, cstr!("spin"));
+    lua::pushnumber(state, iterations);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn finishes_within_budget(state: lua::lua_State) -> Result<(), LError> {
+    let top = lua::gettop(state);
+    push_spin(state, 100.0)?;
+    let budget = Budget {
+        instructions: Some(100_000),
+        wall_time: Some(Duration::from_secs(10)),
+    };
+    lua::pcall_with_budget(state, 1, 1, budget)?;
+    assert_eq!(lua::tonumber(state, -1), 5050.0);
+    lua::pop!(state, 1);
+    assert_eq!(lua::gettop(state), top);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn stops_at_the_instruction_limit(state: lua::lua_State) -> Result<(), LError> {
+    let top = lua::gettop(state);
+    push_spin(state, 1e12)?;
+    let budget = Budget {
+        instructions: Some(10_000),
+        wall_time: None,
+    };
+    let result = lua::pcall_with_budget(state, 1, 1, budget);
+    assert!(
+        matches!(result, Err(LError::BudgetExceeded)),
+        "{:?}",
+        result
+    );
+    assert_eq!(lua::gettop(state), top);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn stops_at_the_deadline(state: lua::lua_State) -> Result<(), LError> {
+    push_spin(state, 1e12)?;
+    let budget = Budget {
+        instructions: None,
+        wall_time: Some(Duration::from_millis(20)),
+    };
+    let result = lua::pcall_with_budget(state, 1, 1, budget);
+    assert!(matches!(result, Err(LError::Timeout)), "{:?}", result);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn pcall_does_not_escape(state: lua::lua_State) -> Result<(), LError> {
+    lua::exec::<()>(
+        state,
+        r#"
+        jit.off()
+        function stubborn()
+            while true do
+                pcall(function() for i = 1, 1e12 do end end)
+            end
+        end
+        "#,
+        "test",
+    )?;
+    lua::getglobal!(state, cstr!("stubborn"));
+    let budget = Budget {
+        instructions: Some(50_000),
+        wall_time: Some(Duration::from_secs(10)),
+    };
+    let result = lua::pcall_with_budget(state, 0, 0, budget);
+    assert!(
+        matches!(result, Err(LError::BudgetExceeded)),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn reports_other_errors(state: lua::lua_State) -> Result<(), LError> {
+    lua::exec::<()>(state, "function fail() error('boom') end", "test")?;
+    lua::getglobal!(state, cstr!("fail"));
+    let result = lua::pcall_with_budget(state, 0, 0, Budget::default());
+    assert!(
+        matches!(&result, Err(LError::RuntimeMessage(message)) if message.contains("boom")),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
+
+unsafe extern "C" fn previous_hook(_: lua::lua_State, _: *mut lua::lua_Debug) {}
+
+#[lua_test(no_open)]
+unsafe fn restores_the_previous_hook(state: lua::lua_State) -> Result<(), LError> {
+    lua::sethook(state, Some(previous_hook), lua::MASKCALL, 7);
+    for iterations in [10.0, 1e12] {
+        push_spin(state, iterations)?;
+        let budget = Budget {
+            instructions: Some(10_000),
+            wall_time: None,
+        };
+        let _ = lua::pcall_with_budget(state, 1, 0, budget);
+        let hook = lua::gethook(state).map(|hook| hook as usize);
+        assert_eq!(hook, Some(previous_hook as lua::lua_Hook as usize));
+        assert_eq!(lua::gethookmask(state), lua::MASKCALL);
+        assert_eq!(lua::gethookcount(state), 7);
+    }
+    lua::sethook(state, None, 0, 0);
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use lua_shared::bytecode;
+
+/// Header of a stripped version 2 dump, followed by a prototype of `len` bytes.
+fn stripped_header(len: u8) -> Vec<u8> {
+    vec![0x1b, b'L', b'J', 2, 0x02, len]
+}
+
+#[test]
+fn rejects_counts_larger_than_the_dump() {
+    let mut chunk = stripped_header(11);
+    // flags, params, frame size, upvalues
+    chunk.extend_from_slice(&[0, 0, 0, 0]);
+    // 2^32 - 1 GC constants, no numbers, no instructions
+    chunk.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0, 0]);
+    assert_eq!(chunk.len(), 17);
+    assert!(matches!(
+        bytecode::parse(&chunk),
+        Err(lua_shared::LError::BytecodeError(_))
+    ));
+}
+
+#[test]
+fn rejects_overflowing_line_numbers() {
+    // Not stripped, empty chunk name.
+    let mut chunk = vec![0x1b, b'L', b'J', 2, 0, 0, 13];
+    chunk.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0]);
+    // 1 byte of debug info, first line 2^32 - 1, 1 line
+    chunk.extend_from_slice(&[1, 0xff, 0xff, 0xff, 0xff, 0x0f, 1]);
+    assert!(matches!(
+        bytecode::parse(&chunk),
+        Err(lua_shared::LError::BytecodeError(_))
+    ));
+}
+
+/// Runs against a vendored LuaJIT, whose build directory has `jit.bc`.
+#[cfg(feature = "luajit-vendored")]
+mod jit_bc {
+    use lua_shared::{self as lua, bytecode};
+
+    const CHUNKS: &[&str] = &[
+        "return 1",
+        "local a, b = ... return a + b * 2, a .. 'x\\n\"y', not b",
+        r##"
+local t = { 1, 2.5, "three", nil, x = true, [10] = -4 }
+local function counter(start)
+    local count = start or 0
+    return function(step)
+        count = count + (step or 1)
+        return count
+    end
+end
+for i = 1, #t do
+    if t[i] == nil then break end
+end
+for k, v in pairs(t) do
+    t[k] = v
+end
+while false do end
+repeat local x = 1 until true
+return counter, t, 1e300, -0.5, 2^53, 0x7fffffff, select("#", ...)
+"##,
+        "local f = function(...) return ... end return f(1, 2, 3), { f(4, 5) }",
+    ];
+
+    unsafe fn listings(state: lua::lua_State, code: &str) -> (Vec<u8>, String) {
+        let path = concat!(env!("OUT_DIR"), "/luajit-build/src/?.lua");
+        lua::exec(
+            state,
+            &format!(
+                r#"
+package.path = {:?}
+local bc = require("jit.bc")
+local f = assert(loadstring({:?}, "=test"))
+local out = {{}}
+bc.dump(f, {{ write = function(_, s) out[#out + 1] = s end, flush = function() end }}, true)
+return string.dump(f), table.concat(out)
+"#,
+                path, code
+            ),
+            "roundtrip",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_jit_bc() {
+        unsafe {
+            let state = lua::newstate();
+            lua::Lopenlibs(state);
+            for code in CHUNKS {
+                let (chunk, expected) = listings(state, code);
+                assert!(expected.starts_with("-- BYTECODE --"));
+                let dump = bytecode::parse(&chunk).unwrap();
+                assert_eq!(dump.to_string(), expected, "{}", code);
+            }
+            lua::close(state);
+        }
+    }
+
+    #[test]
+    fn parses_stripped_dumps() {
+        unsafe {
+            let state = lua::newstate();
+            lua::Lopenlibs(state);
+            for code in CHUNKS {
+                let (chunk, stripped): (Vec<u8>, Vec<u8>) = lua::exec(
+                    state,
+                    &format!(
+                        "local f = assert(loadstring({:?})) return string.dump(f), string.dump(f, true)",
+                        code
+                    ),
+                    "stripped",
+                )
+                .unwrap();
+                let full = bytecode::parse(&chunk).unwrap();
+                let stripped = bytecode::parse(&stripped).unwrap();
+                assert!(stripped.is_stripped());
+                assert_eq!(stripped.prototypes.len(), full.prototypes.len());
+                for (stripped, full) in stripped.prototypes.iter().zip(&full.prototypes) {
+                    assert_eq!(stripped.instructions, full.instructions);
+                    assert!(stripped.line_info.is_empty());
+                }
+            }
+            lua::close(state);
+        }
+    }
+}
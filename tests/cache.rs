@@ -0,0 +1,101 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored", feature = "sha256"))]
+
+use std::path::PathBuf;
+
+use lua_shared::{self as lua, testing::lua_test, BytecodeCache, LError};
+
+/// A cache in its own temporary directory, removed when dropped.
+struct TempCache(BytecodeCache);
+
+impl TempCache {
+    fn new(name: &str) -> Self {
+        let directory: PathBuf =
+            std::env::temp_dir().join(format!("lua-shared-cache-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        Self(BytecodeCache::new(directory))
+    }
+}
+
+impl Drop for TempCache {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(self.0.directory());
+    }
+}
+
+/// Loads `source` through the cache and returns the number the chunk returns.
+unsafe fn run(state: lua::lua_State, cache: &BytecodeCache, source: &[u8]) -> Result<f64, LError> {
+    cache.load(state, source, "@cached.lua")?;
+    lua::call(state, 0, 1);
+    let result = lua::tonumber(state, -1);
+    lua::pop!(state, 1);
+    Ok(result)
+}
+
+#[lua_test(no_open)]
+unsafe fn misses_write_entries(state: lua::lua_State) -> Result<(), LError> {
+    let cache = TempCache::new("miss");
+    let path = cache.0.entry_path(state, b"return 1", "@cached.lua");
+    assert!(!path.exists());
+    assert_eq!(run(state, &cache.0, b"return 1")?, 1.0);
+    assert!(std::fs::read(&path).unwrap().starts_with(b"\x1bLJ"));
+    // The key covers the chunk name and the source.
+    assert_ne!(cache.0.entry_path(state, b"return 1", "@other.lua"), path);
+    assert_ne!(cache.0.entry_path(state, b"return 2", "@cached.lua"), path);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn hits_load_the_cached_bytecode(state: lua::lua_State) -> Result<(), LError> {
+    let cache = TempCache::new("hit");
+    assert_eq!(run(state, &cache.0, b"return 1")?, 1.0);
+    // Swap in different bytecode to tell whether the entry is used.
+    let other: Vec<u8> = lua::exec(state, "return string.dump(function() return 2 end)", "test")?;
+    let path = cache.0.entry_path(state, b"return 1", "@cached.lua");
+    std::fs::write(&path, other).unwrap();
+    assert_eq!(run(state, &cache.0, b"return 1")?, 2.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn replaces_broken_entries(state: lua::lua_State) -> Result<(), LError> {
+    let cache = TempCache::new("broken");
+    let path = cache.0.entry_path(state, b"return 1", "@cached.lua");
+    std::fs::create_dir_all(cache.0.directory()).unwrap();
+    std::fs::write(&path, b"garbage").unwrap();
+    assert_eq!(run(state, &cache.0, b"return 1")?, 1.0);
+    assert!(std::fs::read(&path).unwrap().starts_with(b"\x1bLJ"));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn does_not_cache_errors(state: lua::lua_State) {
+    let cache = TempCache::new("errors");
+    let top = lua::gettop(state);
+    let result = cache.0.load(state, b"return +", "@cached.lua");
+    assert!(
+        matches!(result, Err(LError::SyntaxError(_))),
+        "{:?}",
+        result
+    );
+    assert_eq!(lua::gettop(state), top);
+    assert!(!cache
+        .0
+        .entry_path(state, b"return +", "@cached.lua")
+        .exists());
+}
+
+#[lua_test(no_open)]
+unsafe fn clear_removes_entries(state: lua::lua_State) -> Result<(), LError> {
+    let cache = TempCache::new("clear");
+    cache.0.clear().unwrap();
+    run(state, &cache.0, b"return 1")?;
+    let unrelated = cache.0.directory().join("notes.txt");
+    std::fs::write(&unrelated, b"keep me").unwrap();
+    cache.0.clear().unwrap();
+    assert!(!cache
+        .0
+        .entry_path(state, b"return 1", "@cached.lua")
+        .exists());
+    assert!(unrelated.exists());
+    Ok(())
+}
@@ -0,0 +1,155 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{
+    self as lua,
+    class::{self, Class},
+    cstr,
+    testing::lua_test,
+};
+
+struct Entity {
+    health: f64,
+}
+
+unsafe fn register_entity(state: lua::lua_State) -> Result<(), lua::LError> {
+    Class::new("Entity", |_| Ok(Entity { health: 100.0 }))
+        .method("Damage", |state, this: &mut Entity| {
+            this.health -= lua::Lchecknumber(state, 2);
+            Ok(0)
+        })
+        .method("Health", |state, this: &mut Entity| {
+            lua::pushnumber(state, this.health);
+            Ok(1)
+        })
+        .method("Call", |state, _: &mut Entity| {
+            // Runs Lua code that can reach the same instance again.
+            lua::Lcheckstack(state, 1, std::ptr::null());
+            lua::pushvalue(state, 2);
+            lua::pushvalue(state, 1);
+            lua::call(state, 1, 0);
+            Ok(0)
+        })
+        .register(state)?;
+    lua::setglobal!(state, cstr!("Entity"));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn subclasses_override_methods(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_entity(state)?;
+    lua::exec::<()>(
+        state,
+        r#"
+        Zombie = Entity:extend("Zombie")
+        function Zombie:init(name) self.name = name end
+        function Zombie:Think() self:Damage(1) end
+        zombie = Zombie("bob")
+        "#,
+        "test",
+    )?;
+    lua::getglobal!(state, cstr!("zombie"));
+    class::call_method::<()>(state, -1, "Think", 0)?;
+    assert_eq!(class::get::<Entity>(state, -1).unwrap().health, 99.0);
+    lua::pop!(state, 1);
+    let name: String = lua::exec(state, "return zombie.name", "test")?;
+    assert_eq!(name, "bob");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn methods_recover_from_lua_errors(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_entity(state)?;
+    let (ok, message, health): (bool, String, f64) = lua::exec(
+        state,
+        r#"
+        local entity = Entity()
+        local ok, message = pcall(entity.Damage, entity, "x")
+        entity:Damage(10)
+        return ok, message, entity:Health()
+        "#,
+        "test",
+    )?;
+    assert!(!ok);
+    assert!(message.contains("number expected"), "{}", message);
+    assert_eq!(health, 90.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_reentrant_methods(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_entity(state)?;
+    let (ok, message, health): (bool, String, f64) = lua::exec(
+        state,
+        r#"
+        local entity = Entity()
+        local ok, message = pcall(entity.Call, entity, function(self) self:Damage(1) end)
+        return ok, message, entity:Health()
+        "#,
+        "test",
+    )?;
+    assert!(!ok);
+    assert!(message.contains("already in use"), "{}", message);
+    assert_eq!(health, 100.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn get_rejects_other_values(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_entity(state)?;
+    lua::exec::<()>(state, "plain = {} entity = Entity()", "test")?;
+    lua::getglobal!(state, cstr!("plain"));
+    assert!(class::get::<Entity>(state, -1).is_none());
+    assert!(class::get::<String>(state, -1).is_none());
+    lua::getglobal!(state, cstr!("entity"));
+    assert!(class::get::<String>(state, -1).is_none());
+    assert!(class::get::<Entity>(state, -1).is_some());
+    lua::pop!(state, 2);
+    Ok(())
+}
+
+static DROPS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+struct Counted;
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[lua_test(no_open)]
+unsafe fn protects_instance_data(state: lua::lua_State) -> Result<(), lua::LError> {
+    Class::new("Counted", |_| Ok(Counted))
+        .method("Ping", |_, _: &mut Counted| Ok(0))
+        .register(state)?;
+    lua::setglobal!(state, cstr!("Counted"));
+    let (visible, protected, ok): (bool, bool, bool) = lua::exec(
+        state,
+        r#"
+        local object = Counted()
+        local visible = false
+        for key, value in pairs(object) do
+            visible = visible or type(value) == "userdata" or type(key) == "userdata"
+        end
+        -- The debug library still reaches everything.
+        local data
+        for _, store in pairs(debug.getregistry()["lua_shared.class"].storage) do
+            data = store[object] or data
+        end
+        local protected = getmetatable(data) == false
+        local gc = debug.getmetatable(data).__gc
+        gc(io.stdout)
+        gc(newproxy())
+        gc(data)
+        gc(data)
+        return visible, protected, (pcall(object.Ping, object))
+        "#,
+        "test",
+    )?;
+    assert!(!visible);
+    assert!(protected);
+    assert!(!ok);
+    lua::exec::<()>(state, "collectgarbage() collectgarbage()", "test")?;
+    assert_eq!(DROPS.load(std::sync::atomic::Ordering::SeqCst), 1);
+    Ok(())
+}
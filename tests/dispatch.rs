@@ -0,0 +1,65 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::time::Duration;
+
+use lua_shared::{
+    self as lua,
+    dispatch::{DispatchError, Dispatcher},
+    testing::lua_test,
+};
+
+#[lua_test(no_open)]
+unsafe fn runs_jobs_from_other_threads(state: lua::lua_State) -> Result<(), lua::LError> {
+    lua::exec::<()>(state, "answer = 42", "test")?;
+    let dispatcher = Dispatcher::new(4);
+    let handle = dispatcher.handle();
+    let worker = std::thread::spawn(move || {
+        handle.call(|state| unsafe { lua::exec::<f64>(state, "return answer", "job").unwrap() })
+    });
+    while !worker.is_finished() {
+        dispatcher.pump(state);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(worker.join().unwrap(), Ok(42.0));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn bounds_the_queue(state: lua::lua_State) {
+    let dispatcher = Dispatcher::new(2);
+    let handle = dispatcher.handle();
+    assert_eq!(handle.try_send(|_| {}), Ok(()));
+    assert_eq!(handle.try_send(|_| {}), Ok(()));
+    assert_eq!(handle.try_send(|_| {}), Err(DispatchError::Full));
+    assert_eq!(
+        handle.send_timeout(|_| {}, Duration::from_millis(1)),
+        Err(DispatchError::Timeout)
+    );
+    assert_eq!(dispatcher.pump_limit(state, 1), 1);
+    assert_eq!(dispatcher.pending(), 1);
+    assert_eq!(dispatcher.pump(state), 1);
+    assert_eq!(dispatcher.pending(), 0);
+}
+
+#[lua_test(no_open)]
+unsafe fn drops_the_reply_of_panicking_jobs(state: lua::lua_State) {
+    let dispatcher = Dispatcher::new(4);
+    let handle = dispatcher.handle();
+    let reply = handle.request(|_| -> i32 { panic!("job failed") }).unwrap();
+    let after = handle.request(|_| 1).unwrap();
+    let top = lua::gettop(state);
+    assert_eq!(dispatcher.pump(state), 2);
+    assert_eq!(lua::gettop(state), top);
+    assert_eq!(reply.try_get(), Some(Err(DispatchError::Disconnected)));
+    assert_eq!(after.try_get(), Some(Ok(1)));
+}
+
+#[lua_test(no_open)]
+unsafe fn disconnects_when_dropped(_state: lua::lua_State) {
+    let dispatcher = Dispatcher::new(4);
+    let handle = dispatcher.handle();
+    let reply = handle.request(|_| 1).unwrap();
+    drop(dispatcher);
+    assert_eq!(reply.wait(), Err(DispatchError::Disconnected));
+    assert_eq!(handle.send(|_| {}), Err(DispatchError::Disconnected));
+}
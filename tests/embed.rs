@@ -0,0 +1,90 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{
+    self as lua,
+    embed::{Embedded, EmbeddedFile},
+    embed_lua,
+    testing::lua_test,
+    LError,
+};
+
+static MODULES: Embedded = embed_lua!("tests/embed");
+
+#[test]
+fn names_modules_like_require() {
+    let name = |path| EmbeddedFile { path, chunk: b"" }.module_name();
+    assert_eq!(name("mymod/util.lua"), "mymod.util");
+    assert_eq!(name("./mymod/init.lua"), "mymod");
+    assert_eq!(name("compiled.luac"), "compiled");
+    assert_eq!(name("v1.2/file"), "v1.2.file");
+    assert!(MODULES.find("mymod.util").is_some());
+    assert!(MODULES.find("mymod.init").is_none());
+}
+
+#[lua_test(no_open)]
+unsafe fn searcher_finds_embedded_modules(state: lua::lua_State) -> Result<(), LError> {
+    MODULES.install(state);
+    let (name, util, doubled): (String, String, f64) = lua::exec(
+        state,
+        r#"
+        local mymod = require("mymod")
+        return mymod.name, mymod.util.name, mymod.util.double(21)
+        "#,
+        "test",
+    )?;
+    assert_eq!(
+        (name.as_str(), util.as_str(), doubled),
+        ("mymod", "mymod.util", 42.0)
+    );
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn searcher_keeps_preload_first(state: lua::lua_State) -> Result<(), LError> {
+    MODULES.install(state);
+    let name: String = lua::exec(
+        state,
+        r#"
+        package.preload["mymod"] = function() return { name = "preloaded" } end
+        return require("mymod").name
+        "#,
+        "test",
+    )?;
+    assert_eq!(name, "preloaded");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn searcher_reports_missing_and_broken_modules(state: lua::lua_State) -> Result<(), LError> {
+    MODULES.install(state);
+    let (missing, broken): (String, String) = lua::exec(
+        state,
+        r#"
+        return select(2, pcall(require, "nothing")), select(2, pcall(require, "broken"))
+        "#,
+        "test",
+    )?;
+    assert!(
+        missing.contains("no embedded module 'nothing'"),
+        "{}",
+        missing
+    );
+    assert!(
+        broken.contains("error loading module 'broken' from embedded file 'broken.lua'"),
+        "{}",
+        broken
+    );
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn preload_registers_every_file(state: lua::lua_State) -> Result<(), LError> {
+    MODULES.preload(state);
+    let (kind, util): (String, String) = lua::exec(
+        state,
+        r#"return type(package.preload["mymod.util"]), require("mymod.util").name"#,
+        "test",
+    )?;
+    assert_eq!((kind.as_str(), util.as_str()), ("function", "mymod.util"));
+    Ok(())
+}
@@ -0,0 +1 @@
+return { name = "mymod", util = require("mymod.util") }
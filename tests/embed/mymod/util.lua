@@ -0,0 +1,2 @@
+local name = ...
+return { name = name, double = function(x) return x * 2 end }
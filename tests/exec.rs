@@ -0,0 +1,133 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::path::PathBuf;
+
+use lua_shared::{self as lua, testing::lua_test, LError};
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("lua-shared-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn message(result: Result<(), LError>) -> String {
+    match result {
+        Err(LError::RuntimeMessage(message) | LError::SyntaxError(message)) => message,
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[lua_test(no_open)]
+unsafe fn names_chunks(state: lua::lua_State) {
+    let plain = message(lua::exec(state, "error('boom')", "example"));
+    assert!(plain.starts_with("example:1: boom"), "{}", plain);
+    let kept = message(lua::exec(state, "error('boom')", "=kept"));
+    assert!(kept.starts_with("kept:1: boom"), "{}", kept);
+    let path = message(lua::exec(state, "\nerror('boom')", "@addons/file.lua"));
+    assert!(path.starts_with("addons/file.lua:2: boom"), "{}", path);
+    assert!(path.contains("stack traceback"), "{}", path);
+}
+
+#[lua_test(no_open)]
+unsafe fn converts_tuples(state: lua::lua_State) -> Result<(), LError> {
+    let values: (f64, Option<String>, bool, String) =
+        lua::exec(state, "return 1.5, nil, true, 'four'", "test")?;
+    assert_eq!(values, (1.5, None, true, String::from("four")));
+    let missing: (i32, Option<i32>) = lua::exec(state, "return 1", "test")?;
+    assert_eq!(missing, (1, None));
+    let top = lua::gettop(state);
+    let wrong = lua::exec::<(i32, String)>(state, "return 1, {}", "test");
+    assert!(matches!(wrong, Err(LError::TypeError(_))), "{:?}", wrong);
+    assert_eq!(lua::gettop(state), top);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn checks_integers(state: lua::lua_State) -> Result<(), LError> {
+    assert_eq!(lua::exec::<u8>(state, "return 255", "test")?, 255);
+    assert_eq!(lua::exec::<i32>(state, "return -2^31", "test")?, i32::MIN);
+    assert_eq!(lua::exec::<i64>(state, "return 2^53", "test")?, 1 << 53);
+    assert_eq!(lua::exec::<f32>(state, "return 2.5", "test")?, 2.5);
+    for (code, result) in [
+        (
+            "return 2.9",
+            lua::exec::<i32>(state, "return 2.9", "test").map(i64::from),
+        ),
+        (
+            "return -1",
+            lua::exec::<u8>(state, "return -1", "test").map(i64::from),
+        ),
+        (
+            "return 256",
+            lua::exec::<u8>(state, "return 256", "test").map(i64::from),
+        ),
+        (
+            "return 2^31",
+            lua::exec::<i32>(state, "return 2^31", "test").map(i64::from),
+        ),
+        (
+            "return 2^63",
+            lua::exec::<i64>(state, "return 2^63", "test"),
+        ),
+        ("return 0/0", lua::exec::<i64>(state, "return 0/0", "test")),
+        ("return 1/0", lua::exec::<i64>(state, "return 1/0", "test")),
+    ] {
+        assert!(
+            matches!(result, Err(LError::TypeError(_))),
+            "{}: {:?}",
+            code,
+            result
+        );
+    }
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_bytecode(state: lua::lua_State) -> Result<(), LError> {
+    let bytecode: Vec<u8> =
+        lua::exec(state, "return string.dump(function() return 1 end)", "test")?;
+    let file = TempFile::new("bytecode.lua", &bytecode);
+    let result = lua::exec_file::<f64, _>(state, &file.0);
+    assert!(
+        matches!(result, Err(LError::SyntaxError(_))),
+        "{:?}",
+        result
+    );
+    let result = lua::exec::<f64>(state, "\x1bLJ", "test");
+    assert!(
+        matches!(result, Err(LError::SyntaxError(_))),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn runs_files(state: lua::lua_State) -> Result<(), LError> {
+    let file = TempFile::new(
+        "script.lua",
+        b"\xEF\xBB\xBF#!/usr/bin/env luajit\nlocal a, b = ...\nreturn 1, 'two'\n",
+    );
+    let values: (f64, String) = lua::exec_file(state, &file.0)?;
+    assert_eq!(values, (1.0, String::from("two")));
+
+    let failing = TempFile::new("failing.lua", b"#!/usr/bin/env luajit\n\nerror('boom')\n");
+    let message = message(lua::exec_file(state, &failing.0));
+    // Long paths are shortened from the front.
+    assert!(message.contains("failing.lua:3: boom"), "{}", message);
+
+    let missing = lua::exec_file::<(), _>(state, failing.0.with_extension("missing"));
+    assert!(matches!(missing, Err(LError::IoError(_))), "{:?}", missing);
+    Ok(())
+}
@@ -0,0 +1,55 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::time::Duration;
+
+use lua_shared::{self as lua, testing::lua_test, InterruptHandle, LError};
+
+#[lua_test(no_open)]
+unsafe fn interrupts_from_another_thread(state: lua::lua_State) -> Result<(), LError> {
+    lua::exec::<()>(state, "jit.off()", "test")?;
+    let handle = InterruptHandle::new(state);
+    let watchdog = handle.clone();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        watchdog.interrupt();
+    });
+    let result = lua::exec::<()>(state, "while true do end", "test");
+    thread.join().unwrap();
+    assert!(
+        matches!(&result, Err(LError::RuntimeMessage(message)) if message.contains("interrupted")),
+        "{:?}",
+        result
+    );
+    // The hook removes itself, so the state keeps working.
+    assert!(!handle.is_pending());
+    let sum: f64 = lua::exec(state, "return 1 + 2", "test")?;
+    assert_eq!(sum, 3.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn pending_interrupts_hit_the_next_call(state: lua::lua_State) -> Result<(), LError> {
+    let handle = InterruptHandle::new(state);
+    assert!(!handle.is_pending());
+    handle.interrupt();
+    assert!(handle.is_pending());
+    let result = lua::exec::<()>(state, "local x = 1", "test");
+    assert!(
+        matches!(result, Err(LError::RuntimeMessage(_))),
+        "{:?}",
+        result
+    );
+    assert!(!handle.is_pending());
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn cancel_removes_the_interrupt(state: lua::lua_State) -> Result<(), LError> {
+    let handle = InterruptHandle::new(state);
+    handle.interrupt();
+    handle.cancel();
+    assert!(!handle.is_pending());
+    let sum: f64 = lua::exec(state, "return 1 + 2", "test")?;
+    assert_eq!(sum, 3.0);
+    Ok(())
+}
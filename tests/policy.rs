@@ -0,0 +1,117 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{self as lua, cstr, testing::lua_test, LError, LoadMode, LoadPolicy};
+
+unsafe fn bytecode(state: lua::lua_State) -> Result<Vec<u8>, LError> {
+    lua::exec(
+        state,
+        "return string.dump(function() return 42 end)",
+        "test",
+    )
+}
+
+/// Loads `chunk` under `policy` and runs it, leaving the stack as it was.
+unsafe fn run(
+    state: lua::lua_State,
+    chunk: &[u8],
+    policy: &LoadPolicy,
+    signature: Option<&[u8]>,
+) -> Result<f64, LError> {
+    let top = lua::gettop(state);
+    lua::load_buffer_checked(state, chunk, cstr!("=chunk"), policy, signature)?;
+    let status = lua::pcall(state, 0, 1, 0);
+    assert_eq!(status, lua::Status::Ok);
+    let result = lua::tonumber(state, -1);
+    lua::settop(state, top);
+    Ok(result)
+}
+
+fn rejected(result: Result<f64, LError>) -> String {
+    match result {
+        Err(LError::Rejected(reason)) => reason,
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[lua_test(no_open)]
+unsafe fn text_is_always_accepted(state: lua::lua_State) -> Result<(), LError> {
+    let policies = [LoadPolicy::TextOnly, LoadPolicy::Any];
+    for policy in &policies {
+        assert_eq!(policy.check(b"return 42", None)?, LoadMode::Text);
+        assert_eq!(run(state, b"return 42", policy, None)?, 42.0);
+    }
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn text_only_rejects_bytecode(state: lua::lua_State) -> Result<(), LError> {
+    let chunk = bytecode(state)?;
+    let top = lua::gettop(state);
+    let reason = rejected(run(state, &chunk, &LoadPolicy::TextOnly, None));
+    assert_eq!(reason, "binary chunks are not allowed");
+    assert_eq!(lua::gettop(state), top);
+    assert_eq!(run(state, &chunk, &LoadPolicy::Any, None)?, 42.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn loadx_checked_reads_everything(state: lua::lua_State) -> Result<(), LError> {
+    let chunk = bytecode(state)?;
+    let result = lua::loadx_checked(
+        state,
+        &mut chunk.as_slice(),
+        cstr!("=chunk"),
+        &LoadPolicy::TextOnly,
+        None,
+    );
+    assert!(matches!(result, Err(LError::Rejected(_))), "{:?}", result);
+    lua::loadx_checked(
+        state,
+        &mut chunk.as_slice(),
+        cstr!("=chunk"),
+        &LoadPolicy::Any,
+        None,
+    )?;
+    lua::pop!(state, 1);
+    Ok(())
+}
+
+#[cfg(feature = "sha256")]
+#[lua_test(no_open)]
+unsafe fn allowlist_rejects_unknown_bytecode(state: lua::lua_State) -> Result<(), LError> {
+    let chunk = bytecode(state)?;
+    let other: Vec<u8> = lua::exec(state, "return string.dump(function() return 0 end)", "test")?;
+    let policy = LoadPolicy::HashAllowlist([lua::sha256(&other)].into_iter().collect());
+    let reason = rejected(run(state, &chunk, &policy, None));
+    assert_eq!(reason, "binary chunk is not in the allowlist");
+    let policy = LoadPolicy::HashAllowlist([lua::sha256(&chunk)].into_iter().collect());
+    assert_eq!(run(state, &chunk, &policy, None)?, 42.0);
+    Ok(())
+}
+
+#[cfg(feature = "ed25519")]
+#[lua_test(no_open)]
+unsafe fn signed_rejects_unsigned_bytecode(state: lua::lua_State) -> Result<(), LError> {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let chunk = bytecode(state)?;
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let stranger = SigningKey::from_bytes(&[8; 32]);
+    let policy = LoadPolicy::Signed(vec![key.verifying_key()]);
+    assert_eq!(
+        rejected(run(state, &chunk, &policy, None)),
+        "binary chunk is not signed"
+    );
+    assert_eq!(
+        rejected(run(state, &chunk, &policy, Some(b"short"))),
+        "malformed signature"
+    );
+    let forged = stranger.sign(&chunk).to_bytes();
+    assert_eq!(
+        rejected(run(state, &chunk, &policy, Some(&forged))),
+        "binary chunk has no valid signature"
+    );
+    let signature = key.sign(&chunk).to_bytes();
+    assert_eq!(run(state, &chunk, &policy, Some(&signature))?, 42.0);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{
+    self as lua, cstr,
+    sandbox::{self, SandboxBuilder},
+    testing::lua_test,
+    FromLua, LError, Status,
+};
+
+/// Runs `code` in the environment built by `builder` and returns its only result.
+unsafe fn run<R>(state: lua::lua_State, builder: SandboxBuilder, code: &[u8]) -> Result<R, LError>
+where
+    R: FromLua,
+{
+    let top = lua::gettop(state);
+    builder.build(state);
+    let result =
+        sandbox::load(state, &mut &code[..], cstr!("=sandbox"), -1).and_then(|_| match lua::pcall(
+            state, 0, 1, 0,
+        ) {
+            Status::Ok => R::from_lua(state, lua::gettop(state)),
+            _ => Err(LError::RuntimeMessage(lua::pop_message(state))),
+        });
+    lua::settop(state, top);
+    result
+}
+
+#[lua_test(no_open)]
+unsafe fn hides_dangerous_globals(state: lua::lua_State) -> Result<(), LError> {
+    for name in [
+        "debug",
+        "os",
+        "io",
+        "jit",
+        "package",
+        "require",
+        "module",
+        "load",
+        "loadstring",
+        "loadfile",
+        "dofile",
+        "setfenv",
+        "getfenv",
+        "rawset",
+        "getmetatable",
+        "collectgarbage",
+        "newproxy",
+        "string.dump",
+    ] {
+        let code = format!("return type({})", name);
+        let kind: String = run(state, SandboxBuilder::new(), code.as_bytes())?;
+        assert_eq!(kind, "nil", "{} is reachable", name);
+    }
+    let kind: String = run(state, SandboxBuilder::new(), b"return type(_G.print)")?;
+    assert_eq!(kind, "function");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn changes_stay_inside(state: lua::lua_State) -> Result<(), LError> {
+    let escaped: bool = run(
+        state,
+        SandboxBuilder::new(),
+        br#"
+        string.upper = nil
+        table.insert = nil
+        math.pi = 3
+        _G.print = nil
+        leaked = true
+        return _G.leaked
+        "#,
+    )?;
+    assert!(escaped);
+    let intact: bool = lua::exec(
+        state,
+        r#"
+        return type(string.upper) == "function" and type(table.insert) == "function"
+            and math.pi > 3.14 and type(print) == "function" and leaked == nil
+        "#,
+        "test",
+    )?;
+    assert!(intact);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_binary_chunks(state: lua::lua_State) -> Result<(), LError> {
+    lua::exec::<()>(
+        state,
+        "bytecode = string.dump(function() return os end)",
+        "test",
+    )?;
+    lua::getglobal!(state, cstr!("bytecode"));
+    let mut len = 0;
+    let bytecode = std::slice::from_raw_parts(lua::tolstring(state, -1, &mut len), len).to_vec();
+    lua::pop!(state, 1);
+    let result = run::<()>(state, SandboxBuilder::new(), &bytecode);
+    assert!(
+        matches!(result, Err(LError::SyntaxError(_))),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn exposes_only_what_was_asked(state: lua::lua_State) -> Result<(), LError> {
+    let builder = SandboxBuilder::empty()
+        .allow("type")
+        .function("answer", |state| {
+            lua::pushnumber(state, 42.0);
+            Ok(1)
+        })
+        .value("missing", |state| lua::pushnil(state))
+        .allow("pcall")
+        .deny("pcall");
+    let seen: String = run(
+        state,
+        builder,
+        b"return type(answer) .. type(missing) .. type(pcall) .. type(print) .. answer()",
+    )?;
+    assert_eq!(seen, "functionnilnilnil42");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn errors_are_returned(state: lua::lua_State) {
+    let result = run::<()>(state, SandboxBuilder::new(), b"error('boom')");
+    assert!(
+        matches!(&result, Err(LError::RuntimeMessage(message)) if message.contains("boom")),
+        "{:?}",
+        result
+    );
+}
@@ -0,0 +1,59 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{self as lua, cstr, testing::lua_test, State};
+
+/// A state opened on its own thread, closed when dropped.
+struct Owned(lua::lua_State);
+
+unsafe impl Send for Owned {}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        unsafe { lua::close(self.0) };
+    }
+}
+
+fn open_elsewhere() -> Owned {
+    std::thread::spawn(|| unsafe {
+        let state = lua::newstate();
+        State::open(state);
+        Owned(state)
+    })
+    .join()
+    .unwrap()
+}
+
+#[lua_test(no_open)]
+unsafe fn owner_is_recorded_per_state(state: lua::lua_State) {
+    assert!(lua::is_owner_thread(state));
+    let other = open_elsewhere();
+    assert!(!lua::is_owner_thread(other.0));
+    // The state of the test is still ours.
+    assert!(lua::is_owner_thread(state));
+    State::open(other.0);
+    assert!(lua::is_owner_thread(other.0));
+}
+
+#[lua_test(no_open)]
+unsafe fn states_without_owner_are_not_checked(state: lua::lua_State) {
+    let _ = state;
+    let other = std::thread::spawn(|| Owned(lua::newstate()))
+        .join()
+        .unwrap();
+    assert!(lua::is_owner_thread(other.0));
+    lua::check_thread(other.0);
+    State::from_raw(other.0).as_ptr();
+}
+
+#[cfg(debug_assertions)]
+#[lua_test(no_open)]
+unsafe fn functions_refuse_foreign_threads(state: lua::lua_State) -> Result<(), lua::LError> {
+    let _ = state;
+    let other = open_elsewhere();
+    lua::Lopenlibs(other.0);
+    lua::pushfunction(other.0, |_| Ok(0));
+    lua::setglobal!(other.0, cstr!("f"));
+    let message: String = lua::exec(other.0, "return select(2, pcall(f))", "test")?;
+    assert!(message.contains("does not own the state"), "{}", message);
+    Ok(())
+}
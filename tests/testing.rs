@@ -0,0 +1,94 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::path::PathBuf;
+
+use lua_shared::{self as lua, cstr, testing::lua_test};
+
+/// Stands in for the module's entry point, `#[lua_test]` runs it before every test.
+#[no_mangle]
+pub unsafe extern "C" fn gmod13_open(state: lua::lua_State) -> i32 {
+    lua::pushstring(state, cstr!("opened"));
+    lua::setglobal!(state, cstr!("module_state"));
+    0
+}
+
+#[lua_test]
+unsafe fn runs_gmod13_open(state: lua::lua_State) -> Result<(), lua::LError> {
+    let opened: String = lua::exec(state, "return module_state", "test")?;
+    assert_eq!(opened, "opened");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn skips_gmod13_open(state: lua::lua_State) -> Result<(), lua::LError> {
+    let opened: Option<String> = lua::exec(state, "return module_state", "test")?;
+    assert_eq!(opened, None);
+    Ok(())
+}
+
+#[lua_test]
+#[should_panic(expected = "boom")]
+unsafe fn fails_on_lua_errors(state: lua::lua_State) -> Result<(), lua::LError> {
+    lua::exec(state, "error('boom')", "test")
+}
+
+/// Writes `files` into a fresh directory and returns it.
+fn lua_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    for (path, source) in files {
+        let path = directory.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+    directory
+}
+
+#[test]
+fn runs_lua_files() {
+    let directory = lua_files(
+        "lua_files_pass",
+        &[
+            (
+                "math_test.lua",
+                r#"
+describe("math", function()
+    it("adds", function() assert_eq(1 + 1, 2) end)
+    it("compares tables", function() assert_eq({ 1, { x = 2 } }, { 1, { x = 2 } }) end)
+    it("differs", function() assert_ne("a", "b") end)
+end)
+"#,
+            ),
+            (
+                "nested/open_test.lua",
+                r#"it("sees gmod13_open", function() assert_eq(module_state, "opened") end)"#,
+            ),
+            ("helper.lua", "error('not a test file')"),
+        ],
+    );
+    lua::testing::run_lua_files(directory, Some(gmod13_open));
+}
+
+#[test]
+fn reports_failing_lua_files() {
+    let directory = lua_files(
+        "lua_files_fail",
+        &[(
+            "fail_test.lua",
+            r#"
+it("passes", function() end)
+it("fails", function() assert_eq(1, 2, "numbers") end)
+"#,
+        )],
+    );
+    let payload = std::panic::catch_unwind(|| lua::testing::run_lua_files(directory, None))
+        .expect_err("a failing case must fail the test");
+    let message = payload.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with("1 passed, 1 failed"), "{}", message);
+    assert!(message.contains("fail_test.lua :: fails"), "{}", message);
+    assert!(
+        message.contains("numbers: expected 2, got 1"),
+        "{}",
+        message
+    );
+}
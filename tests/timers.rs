@@ -0,0 +1,114 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use lua_shared::{self as lua, testing::lua_test, timers};
+
+/// Records the order callbacks ran in.
+fn log() -> (
+    Rc<RefCell<Vec<&'static str>>>,
+    impl Fn(&'static str) -> timers::TimerResult,
+) {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let writer = log.clone();
+    (log, move |name| {
+        writer.borrow_mut().push(name);
+        Ok(())
+    })
+}
+
+#[lua_test(no_open)]
+unsafe fn fires_in_deadline_order(state: lua::lua_State) {
+    timers::clear();
+    let (log, push) = log();
+    let push = Rc::new(push);
+    let (first, second, third) = (push.clone(), push.clone(), push);
+    timers::after(Duration::from_millis(2), move |_| second("second"));
+    timers::after(Duration::from_millis(4), move |_| third("third"));
+    timers::after(Duration::ZERO, move |_| first("first"));
+    timers::pump(state);
+    std::thread::sleep(Duration::from_millis(5));
+    timers::pump(state);
+    assert_eq!(*log.borrow(), ["first", "second", "third"]);
+}
+
+#[lua_test(no_open)]
+unsafe fn cancel_drops_the_callback(state: lua::lua_State) {
+    timers::clear();
+    let captured = Rc::new(());
+    let held = captured.clone();
+    let handle = timers::every(Duration::from_secs(3600), move |_| {
+        let _ = &held;
+        Ok(())
+    });
+    assert_eq!(Rc::strong_count(&captured), 2);
+    handle.cancel();
+    assert!(!handle.is_active());
+    assert_eq!(Rc::strong_count(&captured), 1);
+    timers::pump(state);
+}
+
+#[lua_test(no_open)]
+unsafe fn cancel_from_the_callback(state: lua::lua_State) {
+    timers::clear();
+    let (log, push) = log();
+    let handle = Rc::new(RefCell::new(None::<timers::TimerHandle>));
+    let own = handle.clone();
+    *handle.borrow_mut() = Some(timers::every(Duration::ZERO, move |_| {
+        own.borrow().as_ref().unwrap().cancel();
+        push("tick")
+    }));
+    timers::pump(state);
+    timers::pump(state);
+    assert_eq!(*log.borrow(), ["tick"]);
+    assert!(!handle.borrow().as_ref().unwrap().is_active());
+    // The callback held the last clone of `own`.
+    assert_eq!(Rc::strong_count(&handle), 1);
+}
+
+#[lua_test(no_open)]
+unsafe fn rescheduled_timers_wait_for_the_next_pump(state: lua::lua_State) {
+    timers::clear();
+    let (log, push) = log();
+    let push = Rc::new(push);
+    let nested = push.clone();
+    timers::after(Duration::ZERO, move |_| {
+        timers::after(Duration::ZERO, move |_| nested("nested"));
+        push("outer")
+    });
+    let ticks = Rc::new(RefCell::new(0));
+    let counter = ticks.clone();
+    let every = timers::every(Duration::ZERO, move |_| {
+        *counter.borrow_mut() += 1;
+        Ok(())
+    });
+    timers::pump(state);
+    assert_eq!(*log.borrow(), ["outer"]);
+    assert_eq!(*ticks.borrow(), 1);
+    timers::pump(state);
+    assert_eq!(*log.borrow(), ["outer", "nested"]);
+    assert_eq!(*ticks.borrow(), 2);
+    every.cancel();
+    timers::pump(state);
+    assert_eq!(*ticks.borrow(), 2);
+}
+
+#[lua_test(no_open)]
+unsafe fn reports_errors_and_keeps_going(state: lua::lua_State) -> Result<(), lua::LError> {
+    timers::clear();
+    lua::exec::<()>(
+        state,
+        "reported = {} function ErrorNoHalt(message) reported[#reported + 1] = message end",
+        "test",
+    )?;
+    timers::after(Duration::ZERO, |_| Err("rust error".into()));
+    timers::after(Duration::ZERO, |state| {
+        lua::exec::<()>(state, "error('lua error')", "test")?;
+        Ok(())
+    });
+    timers::pump(state);
+    let reported: (String, String) = lua::exec(state, "return reported[1], reported[2]", "test")?;
+    assert!(reported.0.starts_with("rust error"), "{}", reported.0);
+    assert!(reported.1.contains("lua error"), "{}", reported.1);
+    Ok(())
+}
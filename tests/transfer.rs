@@ -0,0 +1,130 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use lua_shared::{self as lua, cstr, testing::lua_test, LError};
+
+/// A second, independent state, closed when dropped.
+struct Other(lua::lua_State);
+
+impl Other {
+    unsafe fn new() -> Self {
+        let state = lua::newstate();
+        lua::Lopenlibs(state);
+        Self(state)
+    }
+}
+
+impl Drop for Other {
+    fn drop(&mut self) {
+        unsafe { lua::close(self.0) };
+    }
+}
+
+/// Transfers the global `name` of `src` to the global `name` of `dst`.
+unsafe fn transfer_global(
+    src: lua::lua_State,
+    dst: lua::lua_State,
+    name: *const u8,
+    env: Option<i32>,
+) -> Result<(), LError> {
+    lua::getglobal!(src, name);
+    let result = lua::transfer(src, -1, dst, env);
+    lua::pop!(src, 1);
+    result?;
+    lua::setglobal!(dst, name);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn keeps_cycles_and_shared_tables(state: lua::lua_State) -> Result<(), LError> {
+    let other = Other::new();
+    lua::exec::<()>(
+        state,
+        "value = {name = 'root', list = {1, 2, 3}} value.self = value value.alias = value.list",
+        "test",
+    )?;
+    transfer_global(state, other.0, cstr!("value"), None)?;
+    let kept: (bool, bool, String, f64) = lua::exec(
+        other.0,
+        "return value.self == value, value.alias == value.list, value.name, #value.list",
+        "test",
+    )?;
+    assert_eq!(kept, (true, true, String::from("root"), 3.0));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn functions_get_the_given_environment(state: lua::lua_State) -> Result<(), LError> {
+    let other = Other::new();
+    lua::exec::<()>(
+        state,
+        "secret = 'source' function reveal() return secret end value = {reveal = reveal}",
+        "test",
+    )?;
+    lua::exec::<()>(other.0, "secret = 'globals'", "test")?;
+
+    let top = (lua::gettop(state), lua::gettop(other.0));
+    let result = transfer_global(state, other.0, cstr!("value"), None);
+    assert!(matches!(result, Err(LError::TypeError(_))), "{:?}", result);
+    assert_eq!((lua::gettop(state), lua::gettop(other.0)), top);
+
+    lua::exec::<()>(other.0, "env = {secret = 'env'}", "test")?;
+    lua::getglobal!(other.0, cstr!("env"));
+    let env = lua::gettop(other.0);
+    transfer_global(state, other.0, cstr!("value"), Some(env))?;
+    lua::pop!(other.0, 1);
+    let secret: String = lua::exec(other.0, "return value.reveal()", "test")?;
+    assert_eq!(secret, "env");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_what_it_cannot_copy(state: lua::lua_State) -> Result<(), LError> {
+    let other = Other::new();
+    lua::exec::<()>(
+        state,
+        r#"
+        local captured = 1
+        closure = {function() return captured end}
+        native = {print}
+        thread = {coroutine.create(print)}
+        userdata = {newproxy()}
+        "#,
+        "test",
+    )?;
+    lua::createtable(other.0, 0, 0);
+    let env = lua::gettop(other.0);
+    let top = (lua::gettop(state), lua::gettop(other.0));
+    for name in [
+        cstr!("closure"),
+        cstr!("native"),
+        cstr!("thread"),
+        cstr!("userdata"),
+    ] {
+        let result = transfer_global(state, other.0, name, Some(env));
+        assert!(matches!(result, Err(LError::TypeError(_))), "{:?}", result);
+        assert_eq!((lua::gettop(state), lua::gettop(other.0)), top);
+    }
+    lua::pop!(other.0, 1);
+    Ok(())
+}
+
+unsafe fn clone_point(src: lua::lua_State, index: i32, dst: lua::lua_State) -> Result<(), LError> {
+    let point = *lua::touserdata(src, index).cast::<[f64; 2]>();
+    lua::pushnumber(dst, point[0] + point[1]);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn copies_userdata_through_hooks(state: lua::lua_State) -> Result<(), LError> {
+    let other = Other::new();
+    lua::register_clone_hook("transfer.Point", clone_point);
+    let point = lua::newuserdata(state, std::mem::size_of::<[f64; 2]>()).cast::<[f64; 2]>();
+    point.write([1.0, 2.0]);
+    lua::Lnewmetatable(state, cstr!("transfer.Point"));
+    lua::setmetatable(state, -2);
+    lua::setglobal!(state, cstr!("point"));
+    transfer_global(state, other.0, cstr!("point"), None)?;
+    let sum: f64 = lua::exec(other.0, "return point", "test")?;
+    assert_eq!(sum, 3.0);
+    Ok(())
+}
@@ -0,0 +1,284 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::{
+    fmt,
+    ops::{Add, Mul, Neg},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use lua_shared::{
+    self as lua, cstr,
+    testing::lua_test,
+    userdata::{self, Get, Metatable, UserData},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+struct Vector(f64, f64);
+
+impl UserData for Vector {
+    const NAME: &'static str = "Vector";
+}
+
+impl Add for Vector {
+    type Output = Vector;
+    fn add(self, other: Vector) -> Vector {
+        Vector(self.0 + other.0, self.1 + other.1)
+    }
+}
+
+impl Mul<f64> for Vector {
+    type Output = Vector;
+    fn mul(self, scale: f64) -> Vector {
+        Vector(self.0 * scale, self.1 * scale)
+    }
+}
+
+impl Mul<Vector> for f64 {
+    type Output = Vector;
+    fn mul(self, vector: Vector) -> Vector {
+        vector * self
+    }
+}
+
+impl Neg for Vector {
+    type Output = Vector;
+    fn neg(self) -> Vector {
+        Vector(-self.0, -self.1)
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Vector({}, {})", self.0, self.1)
+    }
+}
+
+impl Get<usize> for Vector {
+    type Output = f64;
+    fn get(&self, key: usize) -> Option<f64> {
+        match key {
+            1 => Some(self.0),
+            2 => Some(self.1),
+            _ => None,
+        }
+    }
+}
+
+impl Get<String> for Vector {
+    type Output = f64;
+    fn get(&self, key: String) -> Option<f64> {
+        match key.as_str() {
+            "x" => Some(self.0),
+            "y" => Some(self.1),
+            _ => None,
+        }
+    }
+}
+
+unsafe fn register_vector(state: lua::lua_State) {
+    Metatable::<Vector>::new()
+        .add::<Vector, Vector>()
+        .mul::<Vector, f64>()
+        .mul::<f64, Vector>()
+        .unm()
+        .eq()
+        .ord()
+        .display()
+        .index::<usize>()
+        .index::<String>()
+        .method("Length", |state, this: &mut Vector| {
+            lua::pushnumber(state, this.0.hypot(this.1));
+            Ok(1)
+        })
+        .method("Scale", |state, this: &mut Vector| {
+            let scale = lua::Lchecknumber(state, 2);
+            *this = *this * scale;
+            Ok(0)
+        })
+        .method("Call", |state, _: &mut Vector| {
+            // Runs Lua code that can reach the same value again.
+            lua::pushvalue(state, 2);
+            lua::pushvalue(state, 1);
+            lua::call(state, 1, 0);
+            Ok(0)
+        })
+        .call(|state, this: &mut Vector| {
+            lua::pushnumber(state, this.0 + this.1);
+            Ok(1)
+        })
+        .register(state);
+    lua::pushfunction(state, |state| {
+        userdata::push(
+            state,
+            Vector(lua::Lchecknumber(state, 1), lua::Lchecknumber(state, 2)),
+        );
+        Ok(1)
+    });
+    lua::setglobal!(state, cstr!("Vector"));
+}
+
+#[lua_test(no_open)]
+unsafe fn maps_operators(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    let sum: bool = lua::exec(
+        state,
+        "return Vector(1, 2) + Vector(3, 4) == Vector(4, 6)",
+        "test",
+    )?;
+    assert!(sum);
+    let (lt, le, gt): (bool, bool, bool) = lua::exec(
+        state,
+        "return Vector(1, 2) < Vector(2, 0), Vector(1, 2) <= Vector(1, 2), Vector(1, 2) > Vector(2, 0)",
+        "test",
+    )?;
+    assert!(lt && le && !gt);
+    let text: String = lua::exec(state, "return tostring(-Vector(1, 2))", "test")?;
+    assert_eq!(text, "Vector(-1, -2)");
+    lua::exec::<()>(state, "v = Vector(1, 2) + Vector(1, 1)", "test")?;
+    lua::getglobal!(state, cstr!("v"));
+    assert_eq!(
+        userdata::check::<Vector>(state, -1),
+        Some(&mut Vector(2.0, 3.0))
+    );
+    lua::pop!(state, 1);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn picks_the_implementation_matching_both_operands(
+    state: lua::lua_State,
+) -> Result<(), lua::LError> {
+    register_vector(state);
+    let (left, right): (bool, bool) = lua::exec(
+        state,
+        "return Vector(1, 2) * 2 == Vector(2, 4), 3 * Vector(1, 2) == Vector(3, 6)",
+        "test",
+    )?;
+    assert!(left && right);
+    let message = lua::exec::<()>(state, "return Vector(1, 2) * Vector(1, 2)", "test")
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("attempt to perform arithmetic (__mul) on userdata and userdata"));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn indexes_through_get(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    let (first, second, x, y): (f64, f64, f64, f64) = lua::exec(
+        state,
+        "local v = Vector(1, 2) return v[1], v[2], v.x, v.y",
+        "test",
+    )?;
+    assert_eq!((first, second, x, y), (1.0, 2.0, 1.0, 2.0));
+    // Numbers that don't fit the key type are not truncated or saturated.
+    let missing: bool = lua::exec(
+        state,
+        "local v = Vector(1, 2) return v[3] == nil and v[1.5] == nil and v[-1] == nil and v[2^64] == nil and v.z == nil",
+        "test",
+    )?;
+    assert!(missing);
+    let length: f64 = lua::exec(state, "return Vector(3, 4):Length()", "test")?;
+    assert_eq!(length, 5.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn calls_the_value(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    let sum: f64 = lua::exec(state, "return Vector(1, 2)()", "test")?;
+    assert_eq!(sum, 3.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn methods_recover_from_lua_errors(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    let (failed, length): (bool, f64) = lua::exec(
+        state,
+        r#"
+        local v = Vector(3, 4)
+        local ok = pcall(v.Scale, v, "big")
+        v:Scale(2)
+        return not ok, v:Length()
+        "#,
+        "test",
+    )?;
+    assert!(failed);
+    assert_eq!(length, 10.0);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn refuses_reentrant_methods(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    let message: String = lua::exec(
+        state,
+        r#"
+        local v = Vector(1, 2)
+        local ok, err = pcall(v.Call, v, function(v) v:Scale(2) end)
+        assert(not ok)
+        assert(v() == 3, "the value is usable again")
+        return err
+        "#,
+        "test",
+    )?;
+    assert!(message.contains("cannot call 'Scale', the Vector is already in use by another method"));
+    let refused: bool = lua::exec(
+        state,
+        "local v = Vector(1, 2) local ok = pcall(v.Call, v, function(v) v() end) return not ok",
+        "test",
+    )?;
+    assert!(refused);
+    Ok(())
+}
+
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+struct Counted;
+
+impl UserData for Counted {
+    const NAME: &'static str = "Counted";
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[lua_test(no_open)]
+unsafe fn protects_the_metatable(state: lua::lua_State) -> Result<(), lua::LError> {
+    register_vector(state);
+    Metatable::<Counted>::new()
+        .method("Get", |state, _: &mut Counted| {
+            lua::pushnumber(state, 1.0);
+            Ok(1)
+        })
+        .register(state);
+    userdata::push(state, Counted);
+    lua::setglobal!(state, cstr!("counted"));
+    let hidden: bool = lua::exec(state, "return getmetatable(counted) == false", "test")?;
+    assert!(hidden);
+    lua::exec::<()>(
+        state,
+        r#"
+        local gc = debug.getmetatable(counted).__gc
+        gc(io.stdout)
+        gc(Vector(1, 2))
+        gc({})
+        assert(counted:Get() == 1)
+        get = counted.Get
+        gc(counted)
+        gc(counted)
+        "#,
+        "test",
+    )?;
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    let failed: bool = lua::exec(state, "return not pcall(get, counted)", "test")?;
+    assert!(failed);
+    lua::getglobal!(state, cstr!("counted"));
+    assert!(userdata::check::<Counted>(state, -1).is_none());
+    lua::pop!(state, 1);
+    Ok(())
+}
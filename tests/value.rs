@@ -0,0 +1,111 @@
+#![cfg(all(feature = "testing", feature = "luajit-vendored"))]
+
+use std::rc::Rc;
+
+use lua_shared::{self as lua, cstr, testing::lua_test, LError, Value};
+
+fn key(name: &str) -> Value {
+    Value::String(name.as_bytes().to_vec())
+}
+
+/// Returns the table inside `value`.
+fn table(value: &Value) -> &Rc<std::cell::RefCell<lua::Table>> {
+    match value {
+        Value::Table(table) => table,
+        other => panic!("expected a table, got {:?}", other),
+    }
+}
+
+#[lua_test(no_open)]
+unsafe fn keeps_cycles(state: lua::lua_State) -> Result<(), LError> {
+    let value: Value = lua::exec(
+        state,
+        "local t = {name = 'loop'} t.self = t return t",
+        "test",
+    )?;
+    let outer = table(&value);
+    let inner = outer.borrow().get(&key("self")).cloned().unwrap();
+    assert!(Rc::ptr_eq(outer, table(&inner)));
+    assert_eq!(value, value.clone());
+    assert!(format!("{:?}", value).contains("<cycle"));
+
+    // Pushing recreates the cycle instead of recursing forever.
+    value.push(state);
+    lua::setglobal!(state, cstr!("copy"));
+    let same: bool = lua::exec(
+        state,
+        "return copy.self == copy and copy.name == 'loop'",
+        "test",
+    )?;
+    assert!(same);
+
+    // Break the cycle so the snapshot is freed.
+    outer.borrow_mut().entries.clear();
+    drop(inner);
+    assert_eq!(Rc::strong_count(outer), 1);
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn keeps_shared_tables(state: lua::lua_State) -> Result<(), LError> {
+    let value: Value = lua::exec(
+        state,
+        "local s = {1} return {a = s, b = s, c = {1}}",
+        "test",
+    )?;
+    let outer = table(&value).borrow();
+    let (a, b, c) = (
+        outer.get(&key("a")).unwrap(),
+        outer.get(&key("b")).unwrap(),
+        outer.get(&key("c")).unwrap(),
+    );
+    assert!(Rc::ptr_eq(table(a), table(b)));
+    assert!(!Rc::ptr_eq(table(a), table(c)));
+    assert_eq!(a, c);
+    value.push(state);
+    lua::setglobal!(state, cstr!("copy"));
+    let shared: (bool, bool) =
+        lua::exec(state, "return copy.a == copy.b, copy.a == copy.c", "test")?;
+    assert_eq!(shared, (true, false));
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn truncates_at_the_depth_limit(state: lua::lua_State) -> Result<(), LError> {
+    lua::exec::<()>(state, "deep = {inner = {innermost = {}}}", "test")?;
+    lua::getglobal!(state, cstr!("deep"));
+    let value = Value::snapshot(state, -1, 1);
+    lua::pop!(state, 1);
+    let outer = table(&value).borrow();
+    assert!(!outer.truncated);
+    let inner = outer.get(&key("inner")).unwrap();
+    assert!(table(inner).borrow().truncated);
+    assert_eq!(format!("{:?}", value), "{inner = {...}}");
+    Ok(())
+}
+
+#[lua_test(no_open)]
+unsafe fn keeps_references(state: lua::lua_State) -> Result<(), LError> {
+    let value: Value = lua::exec(
+        state,
+        r#"
+        meta = {}
+        function greet() return "hi" end
+        return setmetatable({greet = greet, co = coroutine.create(greet)}, meta)
+        "#,
+        "test",
+    )?;
+    assert!(matches!(
+        table(&value).borrow().get(&key("greet")),
+        Some(Value::Function(_))
+    ));
+    value.push(state);
+    lua::setglobal!(state, cstr!("copy"));
+    let same: bool = lua::exec(
+        state,
+        "return copy.greet == greet and getmetatable(copy) == meta and type(copy.co) == 'thread'",
+        "test",
+    )?;
+    assert!(same);
+    Ok(())
+}